@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use crate::index::IndexFile;
+use crate::{Key, Md5Hash};
+
+/// Location of an encoded file inside a CDN archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveLocation {
+    /// Archive holding the file, fetched from `data/xx/yy/<archive>`,
+    /// or `patch/xx/yy/<archive>` for a patch archive
+    pub archive: Md5Hash,
+    /// Offset of the file's encoded bytes in the archive
+    pub offset: u64,
+    /// Size of the file's encoded bytes
+    pub size: u64,
+}
+
+impl ArchiveLocation {
+    /// Byte range of the file inside its archive
+    pub fn range(&self) -> std::ops::Range<u64> {
+        self.offset..self.offset + self.size
+    }
+}
+
+/// Resolves encoding keys to their location in CDN archives through the archives' indices
+#[derive(Debug, Default)]
+pub struct ArchiveResolver {
+    /// Locations by encoding key truncated to [`Key::INDEX_SIZE`], along with the full key
+    locations: HashMap<Key, (Key, ArchiveLocation)>,
+    /// Sizes of the truncated keys of the added indices, keys are looked up truncated to each
+    key_sizes: Vec<u8>,
+}
+
+impl ArchiveResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds every entry of the index of `archive`
+    pub fn add_index(&mut self, archive: &Md5Hash, index: &IndexFile) {
+        self.add_key_size(index.footer.key_size);
+        for entry in &index.index_entries {
+            let location = ArchiveLocation {
+                archive: archive.clone(),
+                offset: entry.offset,
+                size: entry.size,
+            };
+            self.insert(&entry.e_key, location);
+        }
+    }
+
+    /// Adds every entry of an archive-group index
+    /// Entries name their archive by its position in `archives`, the CDN config's archive list
+    pub fn add_group_index(&mut self, archives: &[Md5Hash], index: &IndexFile) {
+        self.add_key_size(index.footer.key_size);
+        for entry in &index.index_entries {
+            let archive_index = usize::from(entry.archive_index.unwrap_or_default());
+            let Some(archive) = archives.get(archive_index) else {
+                tracing::warn!("{:?} names unknown archive {archive_index}", entry.e_key);
+                continue;
+            };
+            let location = ArchiveLocation {
+                archive: archive.clone(),
+                offset: entry.offset,
+                size: entry.size,
+            };
+            self.insert(&entry.e_key, location);
+        }
+    }
+
+    fn insert(&mut self, e_key: &Key, location: ArchiveLocation) {
+        self.locations
+            .insert(e_key.truncated(Key::INDEX_SIZE), (e_key.clone(), location));
+    }
+
+    fn add_key_size(&mut self, key_size: u8) {
+        let key_size = key_size.min(Key::INDEX_SIZE);
+        if !self.key_sizes.contains(&key_size) {
+            self.key_sizes.push(key_size);
+        }
+    }
+
+    /// Location of `e_key`, None if it is in none of the added indices
+    /// `e_key` may be shorter or longer than the keys of the indices
+    pub fn resolve(&self, e_key: &Key) -> Option<&ArchiveLocation> {
+        let mut location = self
+            .key_sizes
+            .iter()
+            .filter(|&&size| e_key.size() >= size)
+            .filter_map(|&size| self.locations.get(&e_key.truncated(size)))
+            .find(|(key, _)| key.matches(e_key));
+        if location.is_none() && self.key_sizes.iter().any(|&size| e_key.size() < size) {
+            // indices with longer keys were skipped above, their entries have to be scanned
+            location = self.locations.values().find(|(key, _)| key.matches(e_key));
+        }
+        location.map(|(_, location)| location)
+    }
+
+    /// Number of encoding keys which can be resolved
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{tests::test_key, ARCHIVE_GROUP_OFFSET_BYTES};
+
+    /// Archive index of `count` entries with `key_size` byte keys
+    fn build_index(count: u32, key_size: usize) -> IndexFile {
+        IndexFile::parse(&crate::index::tests::build_index(count, key_size, 4)).unwrap()
+    }
+
+    #[test]
+    fn resolves_keys_of_any_size() {
+        let archive = Md5Hash([0xAA; 16]);
+        for index_key_size in [16, 9] {
+            let index = build_index(1000, index_key_size);
+            let mut resolver = ArchiveResolver::new();
+            resolver.add_index(&archive, &index);
+            assert_eq!(resolver.len(), 1000);
+
+            for key_size in [16, 9, 4] {
+                let e_key = Key::from_slice(&test_key(789)[..key_size]).unwrap();
+                assert_eq!(index.find(&e_key).unwrap().offset, 78900);
+                let location = resolver.resolve(&e_key).unwrap();
+                assert_eq!(location.archive, archive);
+                assert_eq!(location.range(), 78900..78900 + 790);
+            }
+            assert!(resolver.resolve(&Md5Hash(test_key(1000)).into()).is_none());
+        }
+    }
+
+    #[test]
+    fn resolves_archive_group_entries() {
+        let data = crate::index::tests::build_index(300, 16, ARCHIVE_GROUP_OFFSET_BYTES);
+        let index = IndexFile::parse(&data).unwrap();
+        assert!(index.is_archive_group());
+        let entry = &index.index_entries[100];
+        assert_eq!(entry.archive_index, Some(1));
+        assert_eq!((entry.size, entry.offset), (101, 10000));
+
+        // entries in the third archive name an archive missing from the list and are skipped
+        let archives = [Md5Hash([0xA0; 16]), Md5Hash([0xA1; 16])];
+        let mut resolver = ArchiveResolver::new();
+        resolver.add_group_index(&archives, &index);
+        assert_eq!(resolver.len(), 200);
+
+        for i in [0, 100, 298] {
+            let location = resolver.resolve(&Md5Hash(test_key(i)).into()).unwrap();
+            assert_eq!(location.archive, archives[i as usize % 3]);
+            assert_eq!(location.offset, u64::from(i) * 100);
+            assert_eq!(location.size, u64::from(i) + 1);
+        }
+        assert!(resolver.resolve(&Md5Hash(test_key(2)).into()).is_none());
+    }
+
+    #[test]
+    fn rejects_keys_differing_past_the_index_prefix() {
+        let mut resolver = ArchiveResolver::new();
+        resolver.add_index(&Md5Hash([0xAA; 16]), &build_index(100, 16));
+        let mut e_key = test_key(42);
+        e_key[12] = 0;
+        assert!(resolver.resolve(&Md5Hash(e_key).into()).is_none());
+    }
+}
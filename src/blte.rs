@@ -1,9 +1,116 @@
-use binrw::{BinRead, BinResult};
-use flate2::bufread::ZlibDecoder;
-use std::io::Read;
+use binrw::{io::NoSeek, BinRead, BinResult};
+use flate2::{bufread::ZlibDecoder, write::ZlibEncoder, Compress, Compression};
+use md5::{Digest, Md5};
+use std::{
+    io::{Cursor, Read, Write},
+    ops::Range,
+};
+use thiserror::Error;
+
+use crate::{
+    crypto::{Arc4, KeyStore, Salsa20},
+    espec::{BlockSize, ESpec, ZlibBits},
+    lz4::{self, Lz4Error},
+    Md5Hash,
+};
+
+/// Maximum nesting of recursive ('F') frames accepted before decoding is aborted
+pub const MAX_RECURSION_DEPTH: usize = 8;
+
+#[derive(Debug, Error)]
+pub enum BlteError {
+    #[error("io error while decoding blte")]
+    Io(#[from] std::io::Error),
+
+    #[error("error reading blte frame")]
+    BinRead(#[from] binrw::Error),
+
+    #[error("recursive blte frames nested deeper than {0}")]
+    RecursionLimit(usize),
+
+    #[error("missing tact key {0:016X}")]
+    MissingKey(u64),
+
+    #[error("error decoding lz4 chunk")]
+    Lz4(#[from] Lz4Error),
+
+    #[error("checksum mismatch in chunk {chunk}")]
+    ChecksumMismatch { chunk: usize },
+
+    #[error("chunk {chunk} decoded to {actual} bytes, expected {expected}")]
+    SizeMismatch {
+        chunk: usize,
+        expected: u32,
+        actual: usize,
+    },
+
+    #[error("espec blocks leave {0} trailing bytes unencoded")]
+    UncoveredData(usize),
+
+    #[error("blte frame has too many chunks")]
+    TooManyChunks,
+
+    #[error("encoding with espec {0} is not supported")]
+    UnsupportedESpec(String),
+}
+
+impl From<BlteError> for std::io::Error {
+    fn from(error: BlteError) -> Self {
+        match error {
+            BlteError::Io(error) => error,
+            error => std::io::Error::new(std::io::ErrorKind::InvalidData, error),
+        }
+    }
+}
+
+/// Options controlling how chunks are decoded
+#[derive(Clone, Copy)]
+pub struct DecodeOptions<'a> {
+    /// Source of keys for encrypted ('E') chunks
+    /// without one, encrypted chunks fail with [`BlteError::MissingKey`]
+    pub key_store: Option<&'a dyn KeyStore>,
+    /// Whether each chunk is checked against the checksum and decompressed
+    /// size in its [`ChunkInfoEntry`], enabled by default
+    pub verify_checksums: bool,
+}
+
+impl Default for DecodeOptions<'_> {
+    fn default() -> Self {
+        Self {
+            key_store: None,
+            verify_checksums: true,
+        }
+    }
+}
+
+impl<'a> DecodeOptions<'a> {
+    pub fn with_key_store(key_store: &'a dyn KeyStore) -> Self {
+        Self {
+            key_store: Some(key_store),
+            ..Self::default()
+        }
+    }
+}
 
 #[binrw::parser(reader, endian)]
-fn chunk_data_parser(chunk_info_entries: &[ChunkInfoEntry]) -> BinResult<Vec<DataChunk>> {
+fn chunk_data_parser(chunk_info_entries: Option<&[ChunkInfoEntry]>) -> BinResult<Vec<DataChunk>> {
+    let Some(chunk_info_entries) = chunk_info_entries else {
+        // without a chunk table the rest of the stream is one implicit chunk
+        let pos = reader.stream_position()?;
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.is_empty() {
+            return Err(binrw::Error::AssertFail {
+                pos,
+                message: "single chunk blte without any chunk data".to_owned(),
+            });
+        }
+        let compressed_size = data.len() as u32;
+        let data_chunk =
+            DataChunk::read_options(&mut Cursor::new(data), endian, (compressed_size,))?;
+        return Ok(vec![data_chunk]);
+    };
+
     let mut data_chunks: Vec<DataChunk> = Vec::with_capacity(chunk_info_entries.len());
     for chunk_info_entry in chunk_info_entries {
         data_chunks.push(DataChunk::read_options(
@@ -15,37 +122,202 @@ fn chunk_data_parser(chunk_info_entries: &[ChunkInfoEntry]) -> BinResult<Vec<Dat
     Ok(data_chunks)
 }
 
+/// Header of a BLTE encoded stream
+///
+/// Streams with a `header_size` of zero carry no chunk table, the remaining
+/// data is a single implicit chunk
 #[derive(Debug, BinRead)]
 #[br(big, magic = b"BLTE")]
-pub struct BlockTable {
+pub struct BlteHeader {
     pub header_size: u32,
-    pub chunk_info: ChunkInfo,
+    #[br(if(header_size != 0))]
+    pub chunk_info: Option<ChunkInfo>,
 
-    #[br(count = usize::from(chunk_info.chunk_count))]
+    #[br(count = chunk_info.as_ref().map_or(0, |info| usize::from(info.chunk_count)))]
     pub chunk_info_entries: Vec<ChunkInfoEntry>,
-    #[br(parse_with = chunk_data_parser, args (&chunk_info_entries,))]
+}
+
+impl BlteHeader {
+    /// Largest valid header size, the magic, header size and chunk info followed by
+    /// the entries of a full chunk table
+    pub const MAX_SIZE: usize = 12 + 24 * u16::MAX as usize;
+
+    /// Whether this stream has no chunk table and holds a single implicit chunk
+    pub fn is_single_chunk(&self) -> bool {
+        self.header_size == 0
+    }
+
+    /// Total decoded size, unknown for single chunk streams
+    pub fn decoded_size(&self) -> Option<u64> {
+        if self.is_single_chunk() {
+            return None;
+        }
+        Some(
+            self.chunk_info_entries
+                .iter()
+                .map(|entry| u64::from(entry.decompressed_size))
+                .sum(),
+        )
+    }
+
+    /// Maps a range of decoded bytes to the chunks covering it
+    /// The range is clamped to the decoded size, returns None for empty ranges
+    /// and single chunk streams, which can only be decoded as a whole
+    pub fn chunk_span(&self, range: Range<u64>) -> Option<ChunkSpan> {
+        let range = range.start..range.end.min(self.decoded_size()?);
+        if range.is_empty() {
+            return None;
+        }
+
+        let mut first_chunk = None;
+        let mut decoded_offset = 0u64;
+        let mut encoded_offset = u64::from(self.header_size);
+        for (index, entry) in self.chunk_info_entries.iter().enumerate() {
+            let decoded_end = decoded_offset + u64::from(entry.decompressed_size);
+            let encoded_end = encoded_offset + u64::from(entry.compressed_size);
+
+            if first_chunk.is_none() && range.start < decoded_end {
+                first_chunk = Some((index, encoded_offset, decoded_offset));
+            }
+            if range.end <= decoded_end {
+                let (first_index, encoded_start, decoded_start) = first_chunk?;
+                return Some(ChunkSpan {
+                    chunks: first_index..index + 1,
+                    encoded: encoded_start..encoded_end,
+                    decoded: decoded_start..decoded_end,
+                });
+            }
+
+            decoded_offset = decoded_end;
+            encoded_offset = encoded_end;
+        }
+        None
+    }
+
+    /// Decodes `range` from `encoded`, the encoded bytes of the chunks in `span`
+    /// such as the body of an http range request for `span.encoded`
+    pub fn decode_span(
+        &self,
+        span: &ChunkSpan,
+        encoded: &[u8],
+        range: Range<u64>,
+        options: &DecodeOptions,
+    ) -> Result<Vec<u8>, BlteError> {
+        let mut reader = Cursor::new(encoded);
+        let mut decoded = Vec::new();
+        for index in span.chunks.clone() {
+            let chunk_info_entry = &self.chunk_info_entries[index];
+            let data_chunk =
+                DataChunk::read_be_args(&mut reader, (chunk_info_entry.compressed_size,))?;
+            data_chunk.decode_checked(Some(chunk_info_entry), &mut decoded, index, options, 0)?;
+        }
+
+        span.trim_to(&mut decoded, range);
+        Ok(decoded)
+    }
+}
+
+/// Consecutive chunks covering a range of decoded bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkSpan {
+    /// Indices of the covering chunks in the chunk table
+    pub chunks: Range<usize>,
+    /// Offsets of the covering chunks within the encoded stream
+    pub encoded: Range<u64>,
+    /// Decoded offsets covered by the chunks, a superset of the requested range
+    pub decoded: Range<u64>,
+}
+
+impl ChunkSpan {
+    /// Trims `decoded`, the decoded bytes of this span, down to `range`
+    fn trim_to(&self, decoded: &mut Vec<u8>, range: Range<u64>) {
+        let start = range.start.clamp(self.decoded.start, self.decoded.end) - self.decoded.start;
+        let end = range.end.clamp(self.decoded.start, self.decoded.end) - self.decoded.start;
+        decoded.truncate(end as usize);
+        decoded.drain(..(start as usize).min(decoded.len()));
+    }
+}
+
+/// A BLTE encoded stream read fully into memory
+/// The implicit chunk of a single chunk stream is still exposed through `chunk_data`
+#[derive(Debug, BinRead)]
+#[br(big)]
+pub struct BlockTable {
+    pub header: BlteHeader,
+    #[br(parse_with = chunk_data_parser, args (header.chunk_info.as_ref().map(|_| header.chunk_info_entries.as_slice()),))]
     pub chunk_data: Vec<DataChunk>,
 }
 
 impl BlockTable {
-    pub fn decompress(&self) -> Result<Vec<u8>, std::io::Error> {
-        let mut full_data = Vec::new();
+    /// Whether this table has no chunk table and holds a single implicit chunk
+    pub fn is_single_chunk(&self) -> bool {
+        self.header.is_single_chunk()
+    }
 
-        for data_chunk in &self.chunk_data {
-            match data_chunk.encoding_mode {
-                EncodingMode::PlainData => {
-                    full_data.extend_from_slice(&data_chunk.data);
-                }
-                EncodingMode::Zlib => {
-                    let cursor = std::io::Cursor::new(&data_chunk.data);
-                    let mut decoder = ZlibDecoder::new(cursor);
-                    let mut decoded_bytes = Vec::new();
-                    decoder.read_to_end(&mut decoded_bytes)?;
-                    full_data.extend_from_slice(&decoded_bytes);
-                }
-                EncodingMode::Recursive => todo!(),
-                EncodingMode::Encrypted => todo!(),
-            }
+    /// Every data chunk paired with its chunk table entry
+    /// The implicit chunk of a single chunk table has no entry
+    pub fn chunks(&self) -> impl Iterator<Item = (Option<&ChunkInfoEntry>, &DataChunk)> {
+        self.chunk_data
+            .iter()
+            .enumerate()
+            .map(|(index, data_chunk)| (self.header.chunk_info_entries.get(index), data_chunk))
+    }
+
+    /// Decodes every chunk of the table into a single buffer
+    pub fn decompress(&self) -> Result<Vec<u8>, BlteError> {
+        self.decompress_with(&DecodeOptions::default())
+    }
+
+    /// Decodes every chunk of the table into a single buffer using `options`
+    pub fn decompress_with(&self, options: &DecodeOptions) -> Result<Vec<u8>, BlteError> {
+        self.decompress_at_depth(options, 0)
+    }
+
+    /// Decodes only the chunks covering `range` of the decoded data
+    pub fn decompress_range(&self, range: Range<u64>) -> Result<Vec<u8>, BlteError> {
+        self.decompress_range_with(range, &DecodeOptions::default())
+    }
+
+    /// Decodes only the chunks covering `range` of the decoded data using `options`
+    pub fn decompress_range_with(
+        &self,
+        range: Range<u64>,
+        options: &DecodeOptions,
+    ) -> Result<Vec<u8>, BlteError> {
+        if self.is_single_chunk() {
+            let mut decoded = self.decompress_with(options)?;
+            let end = (range.end as usize).min(decoded.len());
+            decoded.truncate(end);
+            decoded.drain(..(range.start as usize).min(end));
+            return Ok(decoded);
+        }
+
+        let Some(span) = self.header.chunk_span(range.clone()) else {
+            return Ok(Vec::new());
+        };
+        let mut decoded = Vec::new();
+        for index in span.chunks.clone() {
+            self.chunk_data[index].decode_checked(
+                self.header.chunk_info_entries.get(index),
+                &mut decoded,
+                index,
+                options,
+                0,
+            )?;
+        }
+
+        span.trim_to(&mut decoded, range);
+        Ok(decoded)
+    }
+
+    fn decompress_at_depth(
+        &self,
+        options: &DecodeOptions,
+        depth: usize,
+    ) -> Result<Vec<u8>, BlteError> {
+        let mut full_data = Vec::new();
+        for (index, (chunk_info_entry, data_chunk)) in self.chunks().enumerate() {
+            data_chunk.decode_checked(chunk_info_entry, &mut full_data, index, options, depth)?;
         }
         Ok(full_data)
     }
@@ -60,6 +332,7 @@ pub struct ChunkInfo {
 
 #[derive(Debug, BinRead)]
 pub struct ChunkInfoEntry {
+    #[br(assert(compressed_size > 0, "empty chunk in chunk table"))]
     pub compressed_size: u32,
     pub decompressed_size: u32,
     pub checksum: [u8; 16],
@@ -75,6 +348,38 @@ pub enum EncodingMode {
     Recursive,
     #[br(magic = b'E')]
     Encrypted,
+    #[br(magic = b'4')]
+    Lz4,
+}
+
+impl EncodingMode {
+    /// The byte identifying this mode at the start of a chunk
+    pub fn magic(&self) -> u8 {
+        match self {
+            EncodingMode::PlainData => b'N',
+            EncodingMode::Zlib => b'Z',
+            EncodingMode::Recursive => b'F',
+            EncodingMode::Encrypted => b'E',
+            EncodingMode::Lz4 => b'4',
+        }
+    }
+}
+
+/// Sub-header preceding the LZ4 blocks of a '4' chunk
+#[derive(Debug, BinRead)]
+#[br(big)]
+pub struct Lz4Header {
+    #[br(assert(version == 1, "unsupported lz4 header version {}", version))]
+    pub version: u8,
+    pub decoded_size: u64,
+    /// Each block decodes to at most `1 << block_shift` bytes
+    #[br(assert(block_shift < 32, "invalid lz4 block shift {}", block_shift))]
+    pub block_shift: u8,
+}
+
+impl Lz4Header {
+    /// Encoded size of the header itself
+    pub const SIZE: usize = 10;
 }
 
 #[derive(Debug, BinRead)]
@@ -82,6 +387,868 @@ pub enum EncodingMode {
 pub struct DataChunk {
     pub encoding_mode: EncodingMode,
 
-    #[br(count = compressed_size - 1)]
+    #[br(parse_with = chunk_bytes_parser, args(compressed_size - 1))]
+    pub data: Vec<u8>,
+}
+
+/// Reads the `size` bytes of a chunk, growing the buffer as they are read
+/// rather than allocating the size declared by the chunk table up front
+#[binrw::parser(reader)]
+fn chunk_bytes_parser(size: u32) -> BinResult<Vec<u8>> {
+    let pos = reader.stream_position()?;
+    let mut data = Vec::new();
+    (&mut *reader)
+        .take(u64::from(size))
+        .read_to_end(&mut data)?;
+    if data.len() != size as usize {
+        return Err(binrw::Error::AssertFail {
+            pos,
+            message: format!("chunk truncated to {} of {size} bytes", data.len()),
+        });
+    }
+    Ok(data)
+}
+
+#[derive(PartialEq, Eq, Debug, BinRead)]
+pub enum Cipher {
+    #[br(magic = b'S')]
+    Salsa20,
+    #[br(magic = b'A')]
+    Arc4,
+}
+
+/// Header preceding the encrypted payload of an 'E' chunk
+#[derive(Debug, BinRead)]
+#[br(little)]
+pub struct EncryptedHeader {
+    #[br(assert(key_name_size == 8, "unsupported key name size {}", key_name_size))]
+    pub key_name_size: u8,
+    pub key_name: u64,
+    #[br(assert(iv_size <= 8, "unsupported iv size {}", iv_size))]
+    pub iv_size: u8,
+    #[br(count = usize::from(iv_size))]
+    pub iv: Vec<u8>,
+    pub cipher: Cipher,
+}
+
+impl EncryptedHeader {
+    /// Encrypted size of the header itself
+    pub fn size(&self) -> usize {
+        1 + usize::from(self.key_name_size) + 1 + usize::from(self.iv_size) + 1
+    }
+
+    /// Decrypts (or encrypts) the payload of the chunk at `chunk_index` in place
+    pub fn apply_cipher(&self, key: &[u8; 16], chunk_index: usize, payload: &mut [u8]) {
+        // the chunk index is mixed into the low bytes of the iv
+        let mut iv = [0u8; 8];
+        iv[..self.iv.len()].copy_from_slice(&self.iv);
+        for (i, b) in iv.iter_mut().take(4).enumerate() {
+            *b ^= ((chunk_index >> (i * 8)) & 0xFF) as u8;
+        }
+
+        match self.cipher {
+            Cipher::Salsa20 => Salsa20::new(key, &iv).apply_keystream(payload),
+            Cipher::Arc4 => {
+                let mut arc4_key = key.to_vec();
+                arc4_key.extend_from_slice(&iv[..self.iv.len()]);
+                Arc4::new(&arc4_key).apply_keystream(payload)
+            }
+        }
+    }
+}
+
+impl DataChunk {
+    /// Decodes this chunk like [`DataChunk::decode_into`], first verifying it
+    /// against its chunk table entry when enabled in `options`
+    fn decode_checked(
+        &self,
+        chunk_info_entry: Option<&ChunkInfoEntry>,
+        output: &mut Vec<u8>,
+        index: usize,
+        options: &DecodeOptions,
+        depth: usize,
+    ) -> Result<(), BlteError> {
+        let chunk_info_entry = chunk_info_entry.filter(|_| options.verify_checksums);
+        if let Some(chunk_info_entry) = chunk_info_entry {
+            if self.checksum() != chunk_info_entry.checksum {
+                return Err(BlteError::ChecksumMismatch { chunk: index });
+            }
+        }
+
+        let decoded_start = output.len();
+        self.decode_into(output, index, options, depth)?;
+
+        if let Some(chunk_info_entry) = chunk_info_entry {
+            let decoded_size = output.len() - decoded_start;
+            if decoded_size != chunk_info_entry.decompressed_size as usize {
+                return Err(BlteError::SizeMismatch {
+                    chunk: index,
+                    expected: chunk_info_entry.decompressed_size,
+                    actual: decoded_size,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// MD5 of the encoded chunk, including its encoding mode byte
+    pub fn checksum(&self) -> [u8; 16] {
+        let mut hasher = Md5::new();
+        hasher.update([self.encoding_mode.magic()]);
+        hasher.update(&self.data);
+        hasher.finalize().into()
+    }
+
+    /// Decodes this chunk, appending the result to `output`
+    /// `index` is the position of the chunk in its table and `depth` the
+    /// number of recursive frames it is nested in
+    fn decode_into(
+        &self,
+        output: &mut Vec<u8>,
+        index: usize,
+        options: &DecodeOptions,
+        depth: usize,
+    ) -> Result<(), BlteError> {
+        match self.encoding_mode {
+            EncodingMode::PlainData => {
+                output.extend_from_slice(&self.data);
+            }
+            EncodingMode::Zlib => {
+                let mut decoder = ZlibDecoder::new(self.data.as_slice());
+                decoder.read_to_end(output)?;
+            }
+            EncodingMode::Recursive => {
+                if depth >= MAX_RECURSION_DEPTH {
+                    return Err(BlteError::RecursionLimit(MAX_RECURSION_DEPTH));
+                }
+                let nested = BlockTable::read(&mut Cursor::new(&self.data))?;
+                output.extend_from_slice(&nested.decompress_at_depth(options, depth + 1)?);
+            }
+            EncodingMode::Encrypted => {
+                if depth >= MAX_RECURSION_DEPTH {
+                    return Err(BlteError::RecursionLimit(MAX_RECURSION_DEPTH));
+                }
+                let header = EncryptedHeader::read(&mut Cursor::new(&self.data))?;
+                let key = options
+                    .key_store
+                    .and_then(|key_store| key_store.get_key(header.key_name))
+                    .ok_or(BlteError::MissingKey(header.key_name))?;
+
+                let mut payload = self.data[header.size()..].to_vec();
+                header.apply_cipher(&key, index, &mut payload);
+
+                // the decrypted payload is itself an encoded chunk
+                let payload_size = payload.len() as u32;
+                let inner = DataChunk::read_be_args(&mut Cursor::new(payload), (payload_size,))?;
+                inner.decode_into(output, index, options, depth + 1)?;
+            }
+            EncodingMode::Lz4 => {
+                let header = Lz4Header::read(&mut Cursor::new(&self.data))?;
+                let block_size = 1usize << header.block_shift;
+                let decoded_end = usize::try_from(header.decoded_size)
+                    .ok()
+                    .and_then(|decoded_size| output.len().checked_add(decoded_size))
+                    .ok_or(Lz4Error(0))?;
+
+                let mut blocks = &self.data[Lz4Header::SIZE..];
+                while output.len() < decoded_end && !blocks.is_empty() {
+                    let remaining = decoded_end - output.len();
+                    let consumed =
+                        lz4::decompress_block(blocks, output, remaining.min(block_size))?;
+                    blocks = &blocks[consumed..];
+                }
+                // the blocks ran out before producing the declared size
+                if output.len() != decoded_end {
+                    let consumed = self.data.len() - blocks.len();
+                    return Err(Lz4Error(consumed).into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Streaming BLTE decoder
+///
+/// The header is parsed up front, chunks are then read and decoded one at a
+/// time as the consumer reads, so only a single chunk is held in memory
+pub struct BlteReader<'a, R: Read> {
+    reader: R,
+    options: DecodeOptions<'a>,
+    header: BlteHeader,
+    next_chunk: usize,
+    buffer: Vec<u8>,
+    buffer_pos: usize,
+}
+
+impl<R: Read> BlteReader<'static, R> {
+    /// Parses the BLTE header from `reader`, decoding with default options
+    pub fn new(reader: R) -> Result<Self, BlteError> {
+        Self::with_options(reader, DecodeOptions::default())
+    }
+}
+
+impl<'a, R: Read> BlteReader<'a, R> {
+    /// Parses the BLTE header from `reader`
+    pub fn with_options(mut reader: R, options: DecodeOptions<'a>) -> Result<Self, BlteError> {
+        let header = BlteHeader::read(&mut NoSeek::new(&mut reader))?;
+        Ok(Self {
+            reader,
+            options,
+            header,
+            next_chunk: 0,
+            buffer: Vec::new(),
+            buffer_pos: 0,
+        })
+    }
+
+    pub fn header(&self) -> &BlteHeader {
+        &self.header
+    }
+
+    /// Consumes the decoder, returning the underlying reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads and decodes the next chunk into the internal buffer
+    /// Returns false once every chunk has been decoded
+    fn fill_buffer(&mut self) -> Result<bool, BlteError> {
+        let index = self.next_chunk;
+        let data_chunk = if self.header.is_single_chunk() {
+            if index > 0 {
+                return Ok(false);
+            }
+            let mut data = Vec::new();
+            self.reader.read_to_end(&mut data)?;
+            let compressed_size = data.len() as u32;
+            DataChunk::read_be_args(&mut Cursor::new(data), (compressed_size,))?
+        } else {
+            let Some(chunk_info_entry) = self.header.chunk_info_entries.get(index) else {
+                return Ok(false);
+            };
+            DataChunk::read_be_args(
+                &mut NoSeek::new(&mut self.reader),
+                (chunk_info_entry.compressed_size,),
+            )?
+        };
+
+        self.buffer.clear();
+        self.buffer_pos = 0;
+        data_chunk.decode_checked(
+            self.header.chunk_info_entries.get(index),
+            &mut self.buffer,
+            index,
+            &self.options,
+            0,
+        )?;
+        self.next_chunk += 1;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for BlteReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.buffer_pos == self.buffer.len() {
+            if !self.fill_buffer()? {
+                return Ok(0);
+            }
+        }
+
+        let available = &self.buffer[self.buffer_pos..];
+        let read_n = available.len().min(buf.len());
+        buf[..read_n].copy_from_slice(&available[..read_n]);
+        self.buffer_pos += read_n;
+        Ok(read_n)
+    }
+}
+
+/// Asynchronous streaming BLTE decoder
+///
+/// Like [`BlteReader`], each chunk is decoded as soon as its bytes have
+/// arrived, letting downloads and decompression overlap
+#[cfg(feature = "async")]
+pub struct AsyncBlteReader<'a, R> {
+    reader: R,
+    options: DecodeOptions<'a>,
+    header: BlteHeader,
+    next_chunk: usize,
+}
+
+#[cfg(feature = "async")]
+impl<R: tokio::io::AsyncRead + Unpin> AsyncBlteReader<'static, R> {
+    /// Parses the BLTE header from `reader`, decoding with default options
+    pub async fn new(reader: R) -> Result<Self, BlteError> {
+        Self::with_options(reader, DecodeOptions::default()).await
+    }
+}
+
+#[cfg(feature = "async")]
+impl<S, E> AsyncBlteReader<'static, tokio_util::io::StreamReader<S, bytes::Bytes>>
+where
+    S: futures_util::Stream<Item = Result<bytes::Bytes, E>> + Unpin,
+    E: Into<std::io::Error>,
+{
+    /// Parses the BLTE header from a stream of byte buffers, such as an http response body
+    pub async fn from_stream(stream: S) -> Result<Self, BlteError> {
+        Self::new(tokio_util::io::StreamReader::new(stream)).await
+    }
+}
+
+#[cfg(feature = "async")]
+impl<'a, R: tokio::io::AsyncRead + Unpin> AsyncBlteReader<'a, R> {
+    /// Parses the BLTE header from `reader`
+    pub async fn with_options(
+        mut reader: R,
+        options: DecodeOptions<'a>,
+    ) -> Result<Self, BlteError> {
+        use tokio::io::AsyncReadExt;
+
+        // magic and header size, the header size includes both
+        let mut header_data = vec![0u8; 8];
+        reader.read_exact(&mut header_data).await?;
+        // checked before trusting the header size, the stream may not be blte at all
+        if &header_data[..4] != b"BLTE" {
+            return Err(BlteError::BinRead(binrw::Error::BadMagic {
+                pos: 0,
+                found: Box::new(header_data[..4].to_vec()),
+            }));
+        }
+        let header_size = u32::from_be_bytes([
+            header_data[4],
+            header_data[5],
+            header_data[6],
+            header_data[7],
+        ]) as usize;
+        if header_size != 0 {
+            if !(12..=BlteHeader::MAX_SIZE).contains(&header_size) {
+                return Err(BlteError::BinRead(binrw::Error::AssertFail {
+                    pos: 4,
+                    message: format!("invalid blte header size {header_size}"),
+                }));
+            }
+            header_data.resize(header_size, 0);
+            reader.read_exact(&mut header_data[8..]).await?;
+        }
+
+        let header = BlteHeader::read(&mut Cursor::new(header_data))?;
+        Ok(Self {
+            reader,
+            options,
+            header,
+            next_chunk: 0,
+        })
+    }
+
+    pub fn header(&self) -> &BlteHeader {
+        &self.header
+    }
+
+    /// Consumes the decoder, returning the underlying reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads and decodes the next chunk
+    /// Returns None once every chunk has been decoded
+    pub async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, BlteError> {
+        use tokio::io::AsyncReadExt;
+
+        let index = self.next_chunk;
+        let data = if self.header.is_single_chunk() {
+            if index > 0 {
+                return Ok(None);
+            }
+            let mut data = Vec::new();
+            self.reader.read_to_end(&mut data).await?;
+            data
+        } else {
+            let Some(chunk_info_entry) = self.header.chunk_info_entries.get(index) else {
+                return Ok(None);
+            };
+            // grown as bytes arrive rather than allocated up front from the chunk table
+            let compressed_size = u64::from(chunk_info_entry.compressed_size);
+            let mut data = Vec::new();
+            (&mut self.reader)
+                .take(compressed_size)
+                .read_to_end(&mut data)
+                .await?;
+            if data.len() as u64 != compressed_size {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+            data
+        };
+
+        let compressed_size = data.len() as u32;
+        let data_chunk = DataChunk::read_be_args(&mut Cursor::new(data), (compressed_size,))?;
+        let mut decoded = Vec::new();
+        data_chunk.decode_checked(
+            self.header.chunk_info_entries.get(index),
+            &mut decoded,
+            index,
+            &self.options,
+            0,
+        )?;
+        self.next_chunk += 1;
+        Ok(Some(decoded))
+    }
+
+    /// Converts the decoder into a stream of decoded chunks
+    pub fn into_stream(
+        self,
+    ) -> impl futures_util::Stream<Item = Result<bytes::Bytes, BlteError>> + 'a
+    where
+        R: 'a,
+    {
+        futures_util::stream::try_unfold(self, |mut reader| async move {
+            let chunk = reader.next_chunk().await?;
+            Ok(chunk.map(|chunk| (bytes::Bytes::from(chunk), reader)))
+        })
+    }
+}
+
+/// A BLTE encoded blob along with its content and encoding keys
+#[derive(Debug)]
+pub struct EncodedBlte {
     pub data: Vec<u8>,
+    /// MD5 of the decoded content
+    pub c_key: Md5Hash,
+    /// MD5 of the chunk table header, or of the whole blob for single chunk streams
+    pub e_key: Md5Hash,
+}
+
+/// BLTE encodes `data` as described by `espec`
+///
+/// A top level 'b' spec produces a chunk table with one chunk per block,
+/// nested block lists are encoded as recursive ('F') frames, any other
+/// spec produces a single chunk stream
+pub fn encode(data: &[u8], espec: &ESpec) -> Result<EncodedBlte, BlteError> {
+    let (encoded, header_size) = encode_frame(data, espec)?;
+    let e_key = match header_size {
+        0 => Md5::digest(&encoded),
+        header_size => Md5::digest(&encoded[..header_size]),
+    };
+    Ok(EncodedBlte {
+        data: encoded,
+        c_key: Md5Hash(Md5::digest(data).into()),
+        e_key: Md5Hash(e_key.into()),
+    })
+}
+
+/// Encodes a full BLTE frame, returning it along with its header size
+fn encode_frame(data: &[u8], espec: &ESpec) -> Result<(Vec<u8>, usize), BlteError> {
+    let ESpec::Blocks(block_specs) = espec else {
+        let mut frame = b"BLTE".to_vec();
+        frame.extend_from_slice(&0u32.to_be_bytes());
+        frame.extend_from_slice(&encode_chunk(data, espec)?);
+        return Ok((frame, 0));
+    };
+
+    // split the data up front as described by the block list
+    let mut blocks = Vec::new();
+    let mut remaining = data;
+    for block_spec in block_specs {
+        match block_spec.size {
+            BlockSize::Single(size) => {
+                blocks.push((split_block(&mut remaining, size), &block_spec.spec))
+            }
+            BlockSize::Repeated {
+                size,
+                count: Some(count),
+            } => {
+                for _ in 0..count {
+                    blocks.push((split_block(&mut remaining, size), &block_spec.spec));
+                }
+            }
+            BlockSize::Repeated { size, count: None } => {
+                while !remaining.is_empty() && size > 0 {
+                    blocks.push((split_block(&mut remaining, size), &block_spec.spec));
+                }
+            }
+            BlockSize::Remaining => {
+                blocks.push((split_block(&mut remaining, u64::MAX), &block_spec.spec))
+            }
+        }
+    }
+    if !remaining.is_empty() {
+        return Err(BlteError::UncoveredData(remaining.len()));
+    }
+    blocks.retain(|(block, _)| !block.is_empty());
+
+    let chunk_count = u16::try_from(blocks.len()).map_err(|_| BlteError::TooManyChunks)?;
+    let header_size = 8 + 4 + 24 * blocks.len();
+    let mut chunk_info_entries = Vec::with_capacity(header_size);
+    let mut chunk_data = Vec::new();
+    for (block, spec) in blocks {
+        let chunk = encode_chunk(block, spec)?;
+        chunk_info_entries.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+        chunk_info_entries.extend_from_slice(&(block.len() as u32).to_be_bytes());
+        chunk_info_entries.extend_from_slice(&Md5::digest(&chunk));
+        chunk_data.extend_from_slice(&chunk);
+    }
+
+    let mut frame = Vec::with_capacity(header_size + chunk_data.len());
+    frame.extend_from_slice(b"BLTE");
+    frame.extend_from_slice(&(header_size as u32).to_be_bytes());
+    frame.extend_from_slice(&[0x0F, 0]);
+    frame.extend_from_slice(&chunk_count.to_be_bytes());
+    frame.extend_from_slice(&chunk_info_entries);
+    frame.extend_from_slice(&chunk_data);
+    Ok((frame, header_size))
+}
+
+/// Splits up to `size` bytes off the front of `remaining`
+fn split_block<'a>(remaining: &mut &'a [u8], size: u64) -> &'a [u8] {
+    let (block, rest) = remaining.split_at((size as usize).min(remaining.len()));
+    *remaining = rest;
+    block
+}
+
+/// Encodes a single chunk, including its encoding mode byte
+fn encode_chunk(data: &[u8], espec: &ESpec) -> Result<Vec<u8>, BlteError> {
+    match espec {
+        ESpec::None => {
+            let mut chunk = Vec::with_capacity(data.len() + 1);
+            chunk.push(EncodingMode::PlainData.magic());
+            chunk.extend_from_slice(data);
+            Ok(chunk)
+        }
+        ESpec::Zlib { level, bits } => {
+            // flate2 panics on levels and window sizes zlib does not support
+            let level = level.unwrap_or(9);
+            if level > 9 {
+                return Err(BlteError::UnsupportedESpec(espec.to_string()));
+            }
+            let compression = Compression::new(u32::from(level));
+            let compress = match bits {
+                Some(ZlibBits::Bits(bits @ 9..=15)) => {
+                    Compress::new_with_window_bits(compression, true, *bits)
+                }
+                Some(ZlibBits::Bits(_)) => {
+                    return Err(BlteError::UnsupportedESpec(espec.to_string()))
+                }
+                _ => Compress::new(compression, true),
+            };
+            let mut encoder =
+                ZlibEncoder::new_with_compress(vec![EncodingMode::Zlib.magic()], compress);
+            encoder.write_all(data)?;
+            Ok(encoder.finish()?)
+        }
+        ESpec::Blocks(_) => {
+            let (frame, _) = encode_frame(data, espec)?;
+            let mut chunk = Vec::with_capacity(frame.len() + 1);
+            chunk.push(EncodingMode::Recursive.magic());
+            chunk.extend_from_slice(&frame);
+            Ok(chunk)
+        }
+        ESpec::Encrypted { .. } | ESpec::BcPack { .. } | ESpec::GDeflate { .. } => {
+            Err(BlteError::UnsupportedESpec(espec.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Espec nesting `depth` recursive frames inside a top level chunk table
+    fn nested_espec(depth: usize) -> ESpec {
+        let espec = format!("{}z{}", "b:{*=".repeat(depth + 1), "}".repeat(depth + 1));
+        espec.parse().unwrap()
+    }
+
+    fn decode_nested(depth: usize) -> Result<Vec<u8>, BlteError> {
+        let data = b"recursive frames all the way down".repeat(16);
+        let encoded = encode(&data, &nested_espec(depth))?;
+        let decoded = BlockTable::read(&mut Cursor::new(&encoded.data))?.decompress()?;
+        assert_eq!(decoded, data);
+        Ok(decoded)
+    }
+
+    /// Single chunk stream of an lz4 chunk decoding to `decoded_size` bytes
+    fn lz4_stream(decoded_size: u64, block_shift: u8, blocks: &[u8]) -> Vec<u8> {
+        let mut stream = b"BLTE".to_vec();
+        stream.extend_from_slice(&0u32.to_be_bytes());
+        stream.push(b'4');
+        stream.push(1);
+        stream.extend_from_slice(&decoded_size.to_be_bytes());
+        stream.push(block_shift);
+        stream.extend_from_slice(blocks);
+        stream
+    }
+
+    fn decode_stream(stream: &[u8]) -> Result<Vec<u8>, BlteError> {
+        BlockTable::read(&mut Cursor::new(stream))?.decompress()
+    }
+
+    #[test]
+    fn decodes_lz4_blocks() {
+        let stream = lz4_stream(8, 2, b"\x40abcd\x40efgh");
+        assert_eq!(decode_stream(&stream).unwrap(), b"abcdefgh");
+    }
+
+    #[test]
+    fn rejects_short_lz4_chunks() {
+        let stream = lz4_stream(10, 16, b"\x50hello");
+        assert!(matches!(decode_stream(&stream), Err(BlteError::Lz4(_))));
+    }
+
+    #[test]
+    fn rejects_lz4_matches_across_blocks() {
+        // the second block copies the first instead of carrying its own literals
+        let stream = lz4_stream(8, 2, b"\x40abcd\x00\x04\x00");
+        assert!(matches!(decode_stream(&stream), Err(BlteError::Lz4(_))));
+    }
+
+    /// Encodes `data` with `espec`, then decodes it back through every decoder
+    fn round_trip(data: &[u8], espec: &str) {
+        let encoded = encode(data, &espec.parse().unwrap()).unwrap();
+        assert_eq!(encoded.c_key, Md5Hash(Md5::digest(data).into()));
+
+        let table = BlockTable::read(&mut Cursor::new(&encoded.data)).unwrap();
+        assert_eq!(table.decompress().unwrap(), data);
+        let range = 100..data.len() as u64 - 100;
+        assert_eq!(
+            table.decompress_range(range.clone()).unwrap(),
+            &data[range.start as usize..range.end as usize]
+        );
+
+        let mut decoded = Vec::new();
+        BlteReader::new(encoded.data.as_slice())
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    fn sample_data() -> Vec<u8> {
+        (0..20_000u32)
+            .flat_map(|i| (i % 251).to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_single_chunk_streams() {
+        round_trip(&sample_data(), "n");
+        round_trip(&sample_data(), "z");
+        round_trip(&sample_data(), "z:{6,12}");
+    }
+
+    #[test]
+    fn round_trips_chunk_tables() {
+        round_trip(&sample_data(), "b:{1K=n,4K*4=z:3,*=z:{9,15}}");
+        round_trip(&sample_data(), "b:{16K*=z}");
+        round_trip(&sample_data(), "b:{1K=z,*=b:{8K*=n,*=z}}");
+    }
+
+    #[test]
+    fn rejects_unsupported_zlib_parameters() {
+        for espec in ["z:20", "z:{9,16}", "z:{9,8}", "b:{*=z:{10,15}}"] {
+            let result = encode(b"data", &espec.parse().unwrap());
+            assert!(
+                matches!(result, Err(BlteError::UnsupportedESpec(_))),
+                "{espec}"
+            );
+        }
+    }
+
+    /// BLTE stream with a chunk table holding `chunks`, each an encoded chunk and its decoded size
+    fn chunk_table(chunks: &[(Vec<u8>, usize)]) -> Vec<u8> {
+        let header_size = 12 + 24 * chunks.len();
+        let mut stream = b"BLTE".to_vec();
+        stream.extend_from_slice(&(header_size as u32).to_be_bytes());
+        stream.extend_from_slice(&[0x0F, 0]);
+        stream.extend_from_slice(&(chunks.len() as u16).to_be_bytes());
+        for (chunk, decoded_size) in chunks {
+            stream.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+            stream.extend_from_slice(&(*decoded_size as u32).to_be_bytes());
+            stream.extend_from_slice(&Md5::digest(chunk));
+        }
+        for (chunk, _) in chunks {
+            stream.extend_from_slice(chunk);
+        }
+        stream
+    }
+
+    /// Encrypts `inner`, an encoded chunk, as the 'E' chunk at `chunk_index`
+    fn encrypted_chunk(
+        key: &[u8; 16],
+        key_name: u64,
+        cipher: u8,
+        chunk_index: usize,
+        inner: &[u8],
+    ) -> Vec<u8> {
+        let mut header = vec![8];
+        header.extend_from_slice(&key_name.to_le_bytes());
+        header.push(4);
+        header.extend_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+        header.push(cipher);
+
+        let mut payload = inner.to_vec();
+        EncryptedHeader::read(&mut Cursor::new(&header))
+            .unwrap()
+            .apply_cipher(key, chunk_index, &mut payload);
+        let mut chunk = vec![b'E'];
+        chunk.extend_from_slice(&header);
+        chunk.extend_from_slice(&payload);
+        chunk
+    }
+
+    const KEY_NAME: u64 = 0xFA505078126ACB3E;
+    const KEY: [u8; 16] = *b"0123456789abcdef";
+
+    fn encrypted_stream() -> (Vec<u8>, Vec<u8>) {
+        let mut chunks = Vec::new();
+        let mut data = Vec::new();
+        for (index, cipher) in [b'S', b'A', b'S'].into_iter().enumerate() {
+            let plain = format!("chunk {index} encrypted with {}", cipher as char).into_bytes();
+            let mut inner = vec![b'N'];
+            inner.extend_from_slice(&plain);
+            chunks.push((
+                encrypted_chunk(&KEY, KEY_NAME, cipher, index, &inner),
+                plain.len(),
+            ));
+            data.extend_from_slice(&plain);
+        }
+        (chunk_table(&chunks), data)
+    }
+
+    #[test]
+    fn decodes_encrypted_chunks() {
+        let (stream, data) = encrypted_stream();
+        let key_store = HashMap::from([(KEY_NAME, KEY)]);
+        let options = DecodeOptions::with_key_store(&key_store);
+        let table = BlockTable::read(&mut Cursor::new(&stream)).unwrap();
+        assert_eq!(table.decompress_with(&options).unwrap(), data);
+
+        let mut decoded = Vec::new();
+        BlteReader::with_options(stream.as_slice(), options)
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn mixes_chunk_index_into_iv() {
+        let mut header = vec![8];
+        header.extend_from_slice(&KEY_NAME.to_le_bytes());
+        header.push(4);
+        header.extend_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+        header.push(b'S');
+        let header = EncryptedHeader::read(&mut Cursor::new(&header)).unwrap();
+
+        let mut payload = [0u8; 32];
+        header.apply_cipher(&KEY, 0x01020304, &mut payload);
+        let mut expected = [0u8; 32];
+        let iv = [
+            0x12 ^ 0x04,
+            0x34 ^ 0x03,
+            0x56 ^ 0x02,
+            0x78 ^ 0x01,
+            0,
+            0,
+            0,
+            0,
+        ];
+        Salsa20::new(&KEY, &iv).apply_keystream(&mut expected);
+        assert_eq!(payload, expected);
+    }
+
+    #[test]
+    fn rejects_encrypted_chunks_without_their_key() {
+        let (stream, _) = encrypted_stream();
+        let table = BlockTable::read(&mut Cursor::new(&stream)).unwrap();
+        assert!(matches!(
+            table.decompress(),
+            Err(BlteError::MissingKey(KEY_NAME))
+        ));
+
+        let key_store = HashMap::from([(KEY_NAME ^ 1, KEY)]);
+        assert!(matches!(
+            table.decompress_with(&DecodeOptions::with_key_store(&key_store)),
+            Err(BlteError::MissingKey(KEY_NAME))
+        ));
+    }
+
+    #[cfg(feature = "async")]
+    async fn decode_async(stream: &[u8]) -> Result<Vec<u8>, BlteError> {
+        let mut reader = AsyncBlteReader::new(stream).await?;
+        let mut decoded = Vec::new();
+        while let Some(chunk) = reader.next_chunk().await? {
+            decoded.extend_from_slice(&chunk);
+        }
+        Ok(decoded)
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn decodes_chunk_tables_asynchronously() {
+        let data = sample_data();
+        let encoded = encode(&data, &"b:{1K=n,4K*=z}".parse().unwrap()).unwrap();
+        assert_eq!(decode_async(&encoded.data).await.unwrap(), data);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn rejects_streams_which_are_not_blte() {
+        let html = b"<html>\xff\xff\xff\xff</html>";
+        assert!(matches!(
+            decode_async(html).await,
+            Err(BlteError::BinRead(binrw::Error::BadMagic { .. }))
+        ));
+
+        let mut oversized = b"BLTE".to_vec();
+        oversized.extend_from_slice(&(BlteHeader::MAX_SIZE as u32 + 1).to_be_bytes());
+        assert!(matches!(
+            decode_async(&oversized).await,
+            Err(BlteError::BinRead(binrw::Error::AssertFail { .. }))
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_chunks() {
+        let mut stream = chunk_table(&[(vec![b'N'; 10], 9)]);
+        // declare a huge chunk, the stream ends long before it
+        stream[12..16].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(decode_stream(&stream).is_err());
+        let mut decoded = Vec::new();
+        let reader = BlteReader::new(stream.as_slice()).unwrap();
+        assert!(reader.take(u64::MAX).read_to_end(&mut decoded).is_err());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn rejects_truncated_chunks_asynchronously() {
+        let mut stream = chunk_table(&[(vec![b'N'; 10], 9)]);
+        stream[12..16].copy_from_slice(&u32::MAX.to_be_bytes());
+        let error = decode_async(&stream).await.unwrap_err();
+        assert!(matches!(error, BlteError::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn decodes_nested_frames_at_depth_2() {
+        decode_nested(2).unwrap();
+    }
+
+    #[test]
+    fn decodes_nested_frames_at_depth_3() {
+        decode_nested(3).unwrap();
+    }
+
+    #[test]
+    fn decodes_nested_frames_up_to_recursion_limit() {
+        decode_nested(MAX_RECURSION_DEPTH).unwrap();
+    }
+
+    #[test]
+    fn rejects_frames_nested_past_recursion_limit() {
+        let error = decode_nested(MAX_RECURSION_DEPTH + 1).unwrap_err();
+        assert!(matches!(
+            error,
+            BlteError::RecursionLimit(MAX_RECURSION_DEPTH)
+        ));
+    }
 }
@@ -1,8 +1,99 @@
+use std::collections::HashMap;
+
 use crate::{
-    parse::{parse_named_attribute, parse_named_attribute_pair, ParserError},
+    parse::{parse_list, parse_value, ParserError},
     Md5Hash,
 };
 
+/// Key/value config file, such as a build or cdn config
+/// Lines are "key = value", blank lines and '#' comments are skipped
+#[derive(Debug, Clone, Default)]
+pub struct ConfigFile {
+    /// Every key and value in file order, keys such as "patch-entry" may repeat
+    pub entries: Vec<(String, String)>,
+}
+
+impl ConfigFile {
+    pub fn parse(data: &str) -> Result<Self, ParserError> {
+        let mut entries = Vec::new();
+        for (index, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(ParserError::MalformedLine(index + 1))?;
+            entries.push((key.trim().to_owned(), value.trim().to_owned()));
+        }
+        Ok(Self { entries })
+    }
+
+    /// Raw value of `key`, the first if it repeats
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.iter()
+            .find(|(entry_key, _)| *entry_key == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Every raw value of `key`, in order
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.iter()
+            .filter(move |(entry_key, _)| *entry_key == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Every key and raw value, in file order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Parsed value of `key`, None if it is absent
+    pub fn value<T: std::str::FromStr>(&self, key: &str) -> Result<Option<T>, ParserError> {
+        self.get(key).map(parse_value).transpose()
+    }
+
+    /// Parsed value of `key`, which must be present
+    pub fn required<T: std::str::FromStr>(&self, key: &'static str) -> Result<T, ParserError> {
+        self.value(key)?.ok_or(ParserError::MissingAttribute(key))
+    }
+
+    /// Parsed values of a space separated list, empty if it is absent
+    pub fn list<T: std::str::FromStr>(&self, key: &str) -> Result<Vec<T>, ParserError> {
+        self.get(key).map(parse_list).unwrap_or(Ok(Vec::new()))
+    }
+
+    /// Parsed values of a space separated list which must be present
+    pub fn required_list<T: std::str::FromStr>(
+        &self,
+        key: &'static str,
+    ) -> Result<Vec<T>, ParserError> {
+        self.get(key)
+            .map(parse_list)
+            .ok_or(ParserError::MissingAttribute(key))?
+    }
+
+    /// Parsed value of a "{value1} {value2}" pair such as a content and encoding key,
+    /// None if it is absent
+    pub fn pair<T: std::str::FromStr>(&self, key: &str) -> Result<Option<(T, T)>, ParserError> {
+        let Some(value) = self.get(key) else {
+            return Ok(None);
+        };
+        let (value_0, value_1) = value.split_once(' ').ok_or(ParserError::FromStr)?;
+        Ok(Some((parse_value(value_0)?, parse_value(value_1)?)))
+    }
+
+    /// Parsed value of a pair which must be present
+    pub fn required_pair<T: std::str::FromStr>(
+        &self,
+        key: &'static str,
+    ) -> Result<(T, T), ParserError> {
+        self.pair(key)?.ok_or(ParserError::MissingAttribute(key))
+    }
+}
+
 #[derive(Debug)]
 pub struct BuildConfig {
     pub root: Md5Hash,
@@ -10,16 +101,26 @@ pub struct BuildConfig {
     pub install_size: (u32, u32),
     pub download: (Md5Hash, Md5Hash),
     pub download_size: (u32, u32),
+    /// Size manifest, only in newer builds
+    pub size: Option<(Md5Hash, Md5Hash)>,
+    pub size_size: Option<(u32, u32)>,
     pub encoding: (Md5Hash, Md5Hash),
     pub encoding_size: (u32, u32),
+    /// Patch manifest, only in builds which can be patched to
+    pub patch: Option<Md5Hash>,
+    pub patch_size: Option<u64>,
+    pub patch_config: Option<Md5Hash>,
+    pub build_name: Option<String>,
+    pub build_uid: Option<String>,
+    pub build_product: Option<String>,
+    /// Raw value of every "vfs-*" attribute, keyed by the full attribute name
+    pub vfs: HashMap<String, String>,
+    /// Every attribute of the config, including those above
+    pub attributes: ConfigFile,
 }
 
 pub fn parse_build_config(data: &str) -> Result<BuildConfig, ParserError> {
-    let mut lines = data.lines();
-    let _title = lines.next().ok_or(ParserError::Exhausted)?;
-    let _empty = lines.next().ok_or(ParserError::Exhausted)?;
-
-    //     # Build Configuration
+    // # Build Configuration
     //
     // root = 74260639df2c36f256dec1dc99007dee
     // install = cb771e4587a2e7d3df2aa0a0802a1fc9 5707c55346b2bdffdc12587673ca6e78
@@ -30,26 +131,317 @@ pub fn parse_build_config(data: &str) -> Result<BuildConfig, ParserError> {
     // size-size = 6043993 5280643
     // encoding = 81d6b3444dbb7113f69c7625361dbb91 9ea78760c2cfe3c9c3ccd42bf2057f95
     // encoding-size = 23840656 23805555
-    let root = parse_named_attribute("root", &mut lines)?;
-    let install = parse_named_attribute_pair("install", &mut lines)?;
-    let install_size = parse_named_attribute_pair::<u32>("install-size", &mut lines)?;
+    let attributes = ConfigFile::parse(data)?;
+    let vfs = attributes
+        .iter()
+        .filter(|(key, _)| key.starts_with("vfs-"))
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect();
 
-    let download = parse_named_attribute_pair("download", &mut lines)?;
-    let download_size = parse_named_attribute_pair("download-size", &mut lines)?;
+    Ok(BuildConfig {
+        root: attributes.required("root")?,
+        install: attributes.required_pair("install")?,
+        install_size: attributes.required_pair("install-size")?,
+        download: attributes.required_pair("download")?,
+        download_size: attributes.required_pair("download-size")?,
+        size: attributes.pair("size")?,
+        size_size: attributes.pair("size-size")?,
+        encoding: attributes.required_pair("encoding")?,
+        encoding_size: attributes.required_pair("encoding-size")?,
+        patch: attributes.value("patch")?,
+        patch_size: attributes.value("patch-size")?,
+        patch_config: attributes.value("patch-config")?,
+        build_name: attributes.get("build-name").map(str::to_owned),
+        build_uid: attributes.get("build-uid").map(str::to_owned),
+        build_product: attributes.get("build-product").map(str::to_owned),
+        vfs,
+        attributes,
+    })
+}
+
+#[derive(Debug)]
+pub struct CdnConfig {
+    pub archives: Vec<Md5Hash>,
+    /// Size of each archive's index, in the order of `archives`
+    pub archives_index_size: Vec<u64>,
+    /// Merged index of every archive, an archive-group index
+    pub archive_group: Option<Md5Hash>,
+    pub patch_archives: Vec<Md5Hash>,
+    /// Size of each patch archive's index, in the order of `patch_archives`
+    pub patch_archives_index_size: Vec<u64>,
+    /// Merged index of every patch archive
+    pub patch_archive_group: Option<Md5Hash>,
+    /// Index of the files stored loose rather than in archives
+    pub file_index: Option<Md5Hash>,
+    pub file_index_size: Option<u64>,
+    /// Index of the patches stored loose rather than in patch archives
+    pub patch_file_index: Option<Md5Hash>,
+    pub patch_file_index_size: Option<u64>,
+    /// Build configs served by this cdn config
+    pub builds: Vec<Md5Hash>,
+}
 
-    let _size = parse_named_attribute_pair::<Md5Hash>("size", &mut lines)?;
-    let _size_size = parse_named_attribute_pair::<u32>("size-size", &mut lines)?;
+pub fn parse_cdn_config(data: &str) -> Result<CdnConfig, ParserError> {
+    // # CDN Configuration
+    //
+    // archives = {archive ekey} {archive ekey} ...
+    // archives-index-size = {index size} {index size} ...
+    // archive-group = {archive-group index ekey}
+    // patch-archives = {patch archive ekey} ...
+    // patch-archives-index-size = {index size} ...
+    // patch-archive-group = {patch archive-group index ekey}
+    // file-index = {file index ekey}
+    // file-index-size = {index size}
+    // patch-file-index = {patch file index ekey}
+    // patch-file-index-size = {index size}
+    // builds = {build config key} ...
+    let attributes = ConfigFile::parse(data)?;
+    Ok(CdnConfig {
+        archives: attributes.required_list("archives")?,
+        archives_index_size: attributes.list("archives-index-size")?,
+        archive_group: attributes.value("archive-group")?,
+        patch_archives: attributes.list("patch-archives")?,
+        patch_archives_index_size: attributes.list("patch-archives-index-size")?,
+        patch_archive_group: attributes.value("patch-archive-group")?,
+        file_index: attributes.value("file-index")?,
+        file_index_size: attributes.value("file-index-size")?,
+        patch_file_index: attributes.value("patch-file-index")?,
+        patch_file_index_size: attributes.value("patch-file-index-size")?,
+        builds: attributes.list("builds")?,
+    })
+}
 
-    let encoding = parse_named_attribute_pair("encoding", &mut lines)?;
-    let encoding_size = parse_named_attribute_pair("encoding-size", &mut lines)?;
+#[derive(Debug)]
+pub struct PatchConfig {
+    /// Patch manifest of the build
+    pub patch: Md5Hash,
+    pub patch_size: Option<u64>,
+    /// Patches of the build's own manifests, such as its encoding manifest
+    pub entries: Vec<PatchConfigEntry>,
+    /// Every attribute of the config, including those above
+    pub attributes: ConfigFile,
+}
 
-    Ok(BuildConfig {
-        root,
-        install,
-        install_size,
-        download,
-        download_size,
-        encoding,
-        encoding_size,
+/// "patch-entry" of a patch config, a manifest of the build and the patches producing it
+#[derive(Debug)]
+pub struct PatchConfigEntry {
+    /// Manifest being patched, e.g. "encoding"
+    pub kind: String,
+    pub c_key: Md5Hash,
+    pub c_size: u64,
+    pub e_key: Md5Hash,
+    pub e_size: u64,
+    pub espec: String,
+    pub patches: Vec<PatchConfigSource>,
+}
+
+/// Patch from an older version of a patch config entry
+#[derive(Debug)]
+pub struct PatchConfigSource {
+    pub source_e_key: Md5Hash,
+    pub source_size: u64,
+    pub patch_e_key: Md5Hash,
+    pub patch_size: u64,
+}
+
+fn parse_patch_config_entry(value: &str) -> Result<PatchConfigEntry, ParserError> {
+    let values: Vec<&str> = value.split_whitespace().collect();
+    let [kind, c_key, c_size, e_key, e_size, espec, patches @ ..] = values.as_slice() else {
+        return Err(ParserError::Exhausted);
+    };
+    if patches.len() % 4 != 0 {
+        return Err(ParserError::Exhausted);
+    }
+
+    let patches = patches
+        .chunks_exact(4)
+        .map(|patch| {
+            Ok(PatchConfigSource {
+                source_e_key: parse_value(patch[0])?,
+                source_size: parse_value(patch[1])?,
+                patch_e_key: parse_value(patch[2])?,
+                patch_size: parse_value(patch[3])?,
+            })
+        })
+        .collect::<Result<_, ParserError>>()?;
+    Ok(PatchConfigEntry {
+        kind: kind.to_string(),
+        c_key: parse_value(c_key)?,
+        c_size: parse_value(c_size)?,
+        e_key: parse_value(e_key)?,
+        e_size: parse_value(e_size)?,
+        espec: espec.to_string(),
+        patches,
+    })
+}
+
+pub fn parse_patch_config(data: &str) -> Result<PatchConfig, ParserError> {
+    // # Patch Configuration
+    //
+    // patch = 658506593cf1f98a1d9300c418ee5355
+    // patch-size = 22837
+    // patch-entry = encoding 81d6b3444dbb7113f69c7625361dbb91 23840656 9ea78760c2cfe3c9c3ccd42bf2057f95 23805555 b:{22=n,...} {source ekey} {source size} {patch ekey} {patch size} ...
+    let attributes = ConfigFile::parse(data)?;
+    let entries = attributes
+        .get_all("patch-entry")
+        .map(parse_patch_config_entry)
+        .collect::<Result<_, _>>()?;
+
+    Ok(PatchConfig {
+        patch: attributes.required("patch")?,
+        patch_size: attributes.value("patch-size")?,
+        entries,
+        attributes,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUILD_CONFIG: &str = "\
+# Build Configuration
+
+root = 74260639df2c36f256dec1dc99007dee
+install = cb771e4587a2e7d3df2aa0a0802a1fc9 5707c55346b2bdffdc12587673ca6e78
+install-size = 17491 16957
+download = 742820d6e2a8e08c657b2f6402f5beb3 0ee936e6e1c5eda32dad6e133eb24b02
+download-size = 9391314 8189832
+size = 04b685919f85d762322f635a207d85d2 1a98c149a20d884fe4a6d6ec507b0dcd
+size-size = 6043993 5280643
+encoding = 81d6b3444dbb7113f69c7625361dbb91 9ea78760c2cfe3c9c3ccd42bf2057f95
+encoding-size = 23840656 23805555
+build-name = test-build
+vfs-root = 00000000000000000000000000000001 00000000000000000000000000000002
+";
+
+    fn hash(value: &str) -> Md5Hash {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_build_configs() {
+        let config = parse_build_config(BUILD_CONFIG).unwrap();
+        assert_eq!(config.root, hash("74260639df2c36f256dec1dc99007dee"));
+        assert_eq!(
+            config.encoding,
+            (
+                hash("81d6b3444dbb7113f69c7625361dbb91"),
+                hash("9ea78760c2cfe3c9c3ccd42bf2057f95")
+            )
+        );
+        assert_eq!(config.size_size, Some((6043993, 5280643)));
+        assert_eq!(config.build_name.as_deref(), Some("test-build"));
+        assert_eq!(config.vfs.len(), 1);
+        assert!(config.vfs.contains_key("vfs-root"));
+        assert_eq!(config.patch, None);
+    }
+
+    #[test]
+    fn parses_build_configs_in_any_order() {
+        let mut lines: Vec<&str> = BUILD_CONFIG.lines().collect();
+        lines.reverse();
+        let config = parse_build_config(&lines.join("\n")).unwrap();
+        assert_eq!(config.install_size, (17491, 16957));
+        assert_eq!(config.download_size, (9391314, 8189832));
+        assert_eq!(config.attributes.iter().next().unwrap().0, "vfs-root");
+    }
+
+    #[test]
+    fn skips_comments_and_keeps_unknown_keys() {
+        let data = format!("{BUILD_CONFIG}# build-uid = wow\nbuild-attributes = a b c\n");
+        let config = parse_build_config(&data).unwrap();
+        assert_eq!(config.build_uid, None);
+        assert_eq!(config.attributes.get("build-attributes"), Some("a b c"));
+        assert!(config
+            .attributes
+            .iter()
+            .all(|(key, _)| !key.starts_with('#')));
+    }
+
+    #[test]
+    fn parses_build_configs_without_size_manifests() {
+        let data: Vec<&str> = BUILD_CONFIG
+            .lines()
+            .filter(|line| !line.starts_with("size"))
+            .collect();
+        let config = parse_build_config(&data.join("\n")).unwrap();
+        assert_eq!(config.size, None);
+        assert_eq!(config.size_size, None);
+    }
+
+    #[test]
+    fn rejects_build_configs_missing_required_keys() {
+        let data: Vec<&str> = BUILD_CONFIG
+            .lines()
+            .filter(|line| !line.starts_with("encoding "))
+            .collect();
+        assert!(matches!(
+            parse_build_config(&data.join("\n")),
+            Err(ParserError::MissingAttribute("encoding"))
+        ));
+    }
+
+    #[test]
+    fn keeps_repeated_keys_in_order() {
+        let config = ConfigFile::parse("a = 1\nb = 2\na = 3\n\nc=4\n").unwrap();
+        assert_eq!(config.get("a"), Some("1"));
+        assert_eq!(config.get_all("a").collect::<Vec<_>>(), ["1", "3"]);
+        assert_eq!(
+            config.iter().collect::<Vec<_>>(),
+            [("a", "1"), ("b", "2"), ("a", "3"), ("c", "4")]
+        );
+        assert_eq!(config.get("d"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(matches!(
+            ConfigFile::parse("a = 1\n# comment\nb\n"),
+            Err(ParserError::MalformedLine(3))
+        ));
+    }
+
+    #[test]
+    fn parses_patch_entries() {
+        let data = "\
+patch = 00000000000000000000000000000001
+patch-size = 22837
+patch-entry = encoding 00000000000000000000000000000002 100 00000000000000000000000000000003 90 b:{*=z} \
+00000000000000000000000000000004 80 00000000000000000000000000000005 10 \
+00000000000000000000000000000006 70 00000000000000000000000000000007 20
+patch-entry = install 00000000000000000000000000000008 50 00000000000000000000000000000009 40 n
+";
+        let config = parse_patch_config(data).unwrap();
+        assert_eq!(config.patch, hash("00000000000000000000000000000001"));
+        assert_eq!(config.patch_size, Some(22837));
+        assert_eq!(config.entries.len(), 2);
+
+        let encoding = &config.entries[0];
+        assert_eq!(encoding.kind, "encoding");
+        assert_eq!((encoding.c_size, encoding.e_size), (100, 90));
+        assert_eq!(encoding.espec, "b:{*=z}");
+        assert_eq!(encoding.patches.len(), 2);
+        let patch = &encoding.patches[1];
+        assert_eq!(patch.source_e_key, hash("00000000000000000000000000000006"));
+        assert_eq!(patch.source_size, 70);
+        assert_eq!(patch.patch_e_key, hash("00000000000000000000000000000007"));
+        assert_eq!(patch.patch_size, 20);
+
+        assert_eq!(config.entries[1].kind, "install");
+        assert!(config.entries[1].patches.is_empty());
+    }
+
+    #[test]
+    fn rejects_incomplete_patch_entries() {
+        let data = "\
+patch = 00000000000000000000000000000001
+patch-entry = encoding 00000000000000000000000000000002 100 00000000000000000000000000000003 90 b:{*=z} \
+00000000000000000000000000000004 80 00000000000000000000000000000005
+";
+        assert!(matches!(
+            parse_patch_config(data),
+            Err(ParserError::Exhausted)
+        ));
+    }
+}
@@ -0,0 +1,284 @@
+use std::{collections::HashMap, path::Path};
+
+use thiserror::Error;
+
+/// Source of TACT encryption keys, looked up by their 64 bit key name
+pub trait KeyStore: Send + Sync {
+    /// The 16 byte key registered under `key_name`, if known
+    fn get_key(&self, key_name: u64) -> Option<[u8; 16]>;
+}
+
+impl KeyStore for HashMap<u64, [u8; 16]> {
+    fn get_key(&self, key_name: u64) -> Option<[u8; 16]> {
+        self.get(&key_name).copied()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum KeyListError {
+    #[error("io error reading key list")]
+    Io(#[from] std::io::Error),
+
+    #[error("invalid key list entry on line {0}")]
+    InvalidEntry(usize),
+}
+
+/// In memory key store loaded from a TACTKey list
+///
+/// Each line holds a hexadecimal key name followed by the hexadecimal key,
+/// e.g. "FA505078126ACB3E BDC51862ABED79B2DE48C8E7E66C6200".
+/// Blank lines and lines starting with '#' or ';' are ignored
+#[derive(Debug, Default, Clone)]
+pub struct TactKeyList {
+    keys: HashMap<u64, [u8; 16]>,
+}
+
+impl TactKeyList {
+    /// Parses a String representation of a TACTKey list
+    pub fn parse(data: &str) -> Result<Self, KeyListError> {
+        let mut keys = HashMap::new();
+        for (line_number, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            let invalid = || KeyListError::InvalidEntry(line_number + 1);
+            let mut splits = line.split_whitespace();
+            let key_name = splits.next().ok_or_else(invalid)?;
+            let key_name = u64::from_str_radix(key_name, 16).map_err(|_| invalid())?;
+            let key = splits.next().ok_or_else(invalid)?;
+            let mut decoded = [0u8; 16];
+            hex::decode_to_slice(key, &mut decoded).map_err(|_| invalid())?;
+            keys.insert(key_name, decoded);
+        }
+        Ok(Self { keys })
+    }
+
+    /// Reads and parses a TACTKey list file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, KeyListError> {
+        let data = std::fs::read_to_string(path)?;
+        Self::parse(&data)
+    }
+
+    /// Registers a key, replacing any existing key with the same name
+    pub fn insert(&mut self, key_name: u64, key: [u8; 16]) {
+        self.keys.insert(key_name, key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+impl KeyStore for TactKeyList {
+    fn get_key(&self, key_name: u64) -> Option<[u8; 16]> {
+        self.keys.get_key(key_name)
+    }
+}
+
+/// Salsa20/20 keystream using a 16 byte key, as used by TACT
+pub struct Salsa20 {
+    state: [u32; 16],
+    block: [u8; 64],
+    block_offset: usize,
+}
+
+impl Salsa20 {
+    /// "expand 16-byte k"
+    const TAU: [u32; 4] = [0x61707865, 0x3120646e, 0x79622d36, 0x6b206574];
+
+    pub fn new(key: &[u8; 16], nonce: &[u8; 8]) -> Self {
+        let word = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let k = [
+            word(&key[0..]),
+            word(&key[4..]),
+            word(&key[8..]),
+            word(&key[12..]),
+        ];
+        let state = [
+            Self::TAU[0],
+            k[0],
+            k[1],
+            k[2],
+            k[3],
+            Self::TAU[1],
+            word(&nonce[0..]),
+            word(&nonce[4..]),
+            0,
+            0,
+            Self::TAU[2],
+            k[0],
+            k[1],
+            k[2],
+            k[3],
+            Self::TAU[3],
+        ];
+        Self {
+            state,
+            block: [0u8; 64],
+            block_offset: 64,
+        }
+    }
+
+    fn next_block(&mut self) {
+        let mut x = self.state;
+        let quarter = |x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize| {
+            x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+            x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+            x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+            x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+        };
+        for _ in 0..10 {
+            quarter(&mut x, 0, 4, 8, 12);
+            quarter(&mut x, 5, 9, 13, 1);
+            quarter(&mut x, 10, 14, 2, 6);
+            quarter(&mut x, 15, 3, 7, 11);
+            quarter(&mut x, 0, 1, 2, 3);
+            quarter(&mut x, 5, 6, 7, 4);
+            quarter(&mut x, 10, 11, 8, 9);
+            quarter(&mut x, 15, 12, 13, 14);
+        }
+        for (i, word) in x.iter().enumerate() {
+            let word = word.wrapping_add(self.state[i]);
+            self.block[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+
+        let counter = (u64::from(self.state[9]) << 32 | u64::from(self.state[8])).wrapping_add(1);
+        self.state[8] = counter as u32;
+        self.state[9] = (counter >> 32) as u32;
+        self.block_offset = 0;
+    }
+
+    /// XORs the keystream into `data`, encrypting or decrypting it in place
+    pub fn apply_keystream(&mut self, data: &mut [u8]) {
+        for byte in data {
+            if self.block_offset == 64 {
+                self.next_block();
+            }
+            *byte ^= self.block[self.block_offset];
+            self.block_offset += 1;
+        }
+    }
+}
+
+/// ARC4 (RC4) keystream
+pub struct Arc4 {
+    s: [u8; 256],
+    i: u8,
+    j: u8,
+}
+
+impl Arc4 {
+    pub fn new(key: &[u8]) -> Self {
+        let mut s = [0u8; 256];
+        for (i, v) in s.iter_mut().enumerate() {
+            *v = i as u8;
+        }
+        let mut j = 0u8;
+        for i in 0..256 {
+            j = j.wrapping_add(s[i]).wrapping_add(key[i % key.len()]);
+            s.swap(i, usize::from(j));
+        }
+        Self { s, i: 0, j: 0 }
+    }
+
+    /// XORs the keystream into `data`, encrypting or decrypting it in place
+    pub fn apply_keystream(&mut self, data: &mut [u8]) {
+        for byte in data {
+            self.i = self.i.wrapping_add(1);
+            self.j = self.j.wrapping_add(self.s[usize::from(self.i)]);
+            self.s.swap(usize::from(self.i), usize::from(self.j));
+            let k = self.s[usize::from(
+                self.s[usize::from(self.i)].wrapping_add(self.s[usize::from(self.j)]),
+            )];
+            *byte ^= k;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn salsa20_matches_known_answer() {
+        // eSTREAM Salsa20/20, 128 bit key set 1 vector 0
+        let mut key = [0u8; 16];
+        key[0] = 0x80;
+        let mut stream = [0u8; 64];
+        Salsa20::new(&key, &[0; 8]).apply_keystream(&mut stream);
+        assert_eq!(
+            hex::encode_upper(stream),
+            "4DFA5E481DA23EA09A31022050859936DA52FCEE218005164F267CB65F5CFD7F\
+             2B4F97E0FF16924A52DF269515110A07F9E460BC65EF95DA58F740B7D1DBB0AA"
+        );
+    }
+
+    #[test]
+    fn salsa20_keystream_spans_blocks() {
+        let key = [7u8; 16];
+        let mut whole = [0u8; 150];
+        Salsa20::new(&key, &[1; 8]).apply_keystream(&mut whole);
+
+        let mut split = [0u8; 150];
+        let mut salsa = Salsa20::new(&key, &[1; 8]);
+        let (first, second) = split.split_at_mut(37);
+        salsa.apply_keystream(first);
+        salsa.apply_keystream(second);
+        assert_eq!(whole, split);
+    }
+
+    #[test]
+    fn arc4_matches_known_answers() {
+        for (key, plaintext, ciphertext) in [
+            ("Key", "Plaintext", "BBF316E8D940AF0AD3"),
+            ("Wiki", "pedia", "1021BF0420"),
+            ("Secret", "Attack at dawn", "45A01F645FC35B383552544B9BF5"),
+        ] {
+            let mut data = plaintext.as_bytes().to_vec();
+            Arc4::new(key.as_bytes()).apply_keystream(&mut data);
+            assert_eq!(hex::encode_upper(data), ciphertext);
+        }
+    }
+
+    #[test]
+    fn parses_key_lists() {
+        let keys = TactKeyList::parse(
+            "# comment\n\
+             FA505078126ACB3E BDC51862ABED79B2DE48C8E7E66C6200\n\
+             \n\
+             ; another comment\n\
+             ff6c4f1d4d2ca2f5   2ae5a53a3c8e8e4dd0f4d2a9a3d9c6a2\n",
+        )
+        .unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(
+            keys.get_key(0xFA505078126ACB3E)
+                .map(hex::encode_upper)
+                .as_deref(),
+            Some("BDC51862ABED79B2DE48C8E7E66C6200")
+        );
+        assert!(keys.get_key(0xFF6C4F1D4D2CA2F5).is_some());
+        assert!(keys.get_key(0).is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_key_list_entries() {
+        for data in [
+            "FA505078126ACB3E\n",
+            "FA505078126ACB3E BDC518\n",
+            "not-hex BDC51862ABED79B2DE48C8E7E66C6200\n",
+        ] {
+            let data = format!("# header\n{data}");
+            assert!(matches!(
+                TactKeyList::parse(&data),
+                Err(KeyListError::InvalidEntry(2))
+            ));
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use std::io::Cursor;
+use std::str::FromStr;
+
+use binrw::BinRead;
+
+use crate::espec::{ESpec, ESpecError};
+use crate::{
+    decode_key_page, page_for_key, page_matches_checksum, split_espec_block, CeKeyPageEntry,
+    EKeySpecPageEntry, EncodingError, Key, KeyPageEntry, KeyTable, PageVerification,
+};
+
+/// Fixed size header of an encoding manifest
+#[derive(Debug, Clone, BinRead)]
+#[br(big, magic = b"EN")]
+pub struct EncodingHeader {
+    pub version: u8,
+
+    pub ckey_hash_size: u8,
+    pub ekey_hash_size: u8,
+
+    pub ce_page_size_kb: u16,
+    pub e_page_size_kb: u16,
+
+    pub ce_key_table_page_count: u32,
+    pub e_key_table_count: u32,
+
+    _unknown: u8,
+    pub espec_block_size: u32,
+}
+
+impl EncodingHeader {
+    /// Encoded size of the header, including its magic
+    pub const SIZE: usize = 22;
+
+    /// Size of a page index entry of `table`, the first key followed by the page md5
+    fn index_entry_size(&self, table: KeyTable) -> usize {
+        let key_size = match table {
+            KeyTable::CKey => self.ckey_hash_size,
+            KeyTable::EKey => self.ekey_hash_size,
+        };
+        usize::from(key_size) + 16
+    }
+}
+
+/// Zero-copy view over an encoding manifest
+/// Nothing past the header is decoded up front, pages are decoded as they are looked up
+#[derive(Debug, Clone)]
+pub struct EncodingView<'a> {
+    pub header: EncodingHeader,
+    espec_block: &'a [u8],
+    ce_key_table_index: &'a [u8],
+    ce_key_table_pages: &'a [u8],
+    e_key_table_index: &'a [u8],
+    e_key_table_pages: &'a [u8],
+
+    /// How pages failing their checksum are handled when decoded
+    pub page_verification: PageVerification,
+}
+
+/// Splits the next `len` bytes off of `data`
+fn split_section<'a>(
+    data: &mut &'a [u8],
+    len: usize,
+    total: usize,
+) -> Result<&'a [u8], EncodingError> {
+    if data.len() < len {
+        return Err(EncodingError::Truncated {
+            expected: total - data.len() + len,
+            actual: total,
+        });
+    }
+    let (section, rest) = data.split_at(len);
+    *data = rest;
+    Ok(section)
+}
+
+impl<'a> EncodingView<'a> {
+    /// Parses the header of `data` and locates each section without copying them
+    pub fn new(data: &'a [u8]) -> Result<Self, EncodingError> {
+        let header = EncodingHeader::read(&mut Cursor::new(data))?;
+        let total = data.len();
+        let mut rest = &data[EncodingHeader::SIZE..];
+
+        let ce_page_count = header.ce_key_table_page_count as usize;
+        let ce_page_size = usize::from(header.ce_page_size_kb) * 1024;
+        let e_page_count = header.e_key_table_count as usize;
+        let e_page_size = usize::from(header.e_page_size_kb) * 1024;
+
+        let espec_block = split_section(&mut rest, header.espec_block_size as usize, total)?;
+        let ce_index_size = ce_page_count * header.index_entry_size(KeyTable::CKey);
+        let ce_key_table_index = split_section(&mut rest, ce_index_size, total)?;
+        let ce_key_table_pages = split_section(&mut rest, ce_page_count * ce_page_size, total)?;
+        let e_index_size = e_page_count * header.index_entry_size(KeyTable::EKey);
+        let e_key_table_index = split_section(&mut rest, e_index_size, total)?;
+        let e_key_table_pages = split_section(&mut rest, e_page_count * e_page_size, total)?;
+
+        Ok(Self {
+            header,
+            espec_block,
+            ce_key_table_index,
+            ce_key_table_pages,
+            e_key_table_index,
+            e_key_table_pages,
+            page_verification: PageVerification::default(),
+        })
+    }
+
+    /// Entry of a page in the page index
+    fn index_entry(&self, table: KeyTable, page: usize) -> &'a [u8] {
+        let index = match table {
+            KeyTable::CKey => self.ce_key_table_index,
+            KeyTable::EKey => self.e_key_table_index,
+        };
+        let entry_size = self.header.index_entry_size(table);
+        &index[page * entry_size..][..entry_size]
+    }
+}
+
+impl EncodingTables for EncodingView<'_> {
+    fn key_sizes(&self) -> (u8, u8) {
+        (self.header.ckey_hash_size, self.header.ekey_hash_size)
+    }
+
+    fn page_count(&self, table: KeyTable) -> usize {
+        match table {
+            KeyTable::CKey => self.header.ce_key_table_page_count as usize,
+            KeyTable::EKey => self.header.e_key_table_count as usize,
+        }
+    }
+
+    fn page(&self, table: KeyTable, page: usize) -> Option<&[u8]> {
+        let (pages, page_size_kb) = match table {
+            KeyTable::CKey => (self.ce_key_table_pages, self.header.ce_page_size_kb),
+            KeyTable::EKey => (self.e_key_table_pages, self.header.e_page_size_kb),
+        };
+        let page_size = usize::from(page_size_kb) * 1024;
+        pages.get(page * page_size..(page + 1) * page_size)
+    }
+
+    fn first_key(&self, table: KeyTable, page: usize) -> &[u8] {
+        let entry = self.index_entry(table, page);
+        &entry[..entry.len() - 16]
+    }
+
+    fn checksum(&self, table: KeyTable, page: usize) -> &[u8] {
+        let entry = self.index_entry(table, page);
+        &entry[entry.len() - 16..]
+    }
+
+    fn espec_block(&self) -> &[u8] {
+        self.espec_block
+    }
+
+    fn page_verification(&self) -> PageVerification {
+        self.page_verification
+    }
+}
+
+/// The key tables and espec block of an encoding manifest, parsed or viewed in place
+/// Every lookup is implemented once on top of these accessors
+pub trait EncodingTables {
+    /// Sizes of the content and encoding keys stored in the pages
+    fn key_sizes(&self) -> (u8, u8);
+
+    /// Number of pages in `table`
+    fn page_count(&self, table: KeyTable) -> usize;
+
+    /// Raw bytes of a page, without verifying its checksum
+    fn page(&self, table: KeyTable, page: usize) -> Option<&[u8]>;
+
+    /// First key of a page, as stored in the page index
+    fn first_key(&self, table: KeyTable, page: usize) -> &[u8];
+
+    /// Checksum of a page, as stored in the page index
+    fn checksum(&self, table: KeyTable, page: usize) -> &[u8];
+
+    /// Raw espec block, null terminated espec strings
+    fn espec_block(&self) -> &[u8];
+
+    /// How pages failing their checksum are handled when decoded
+    fn page_verification(&self) -> PageVerification;
+
+    /// The null terminated strings of the espec block
+    fn espec_strings(&self) -> Result<Vec<&str>, ESpecError> {
+        split_espec_block(self.espec_block())
+    }
+
+    /// Every espec of the espec block, in order
+    fn especs(&self) -> Result<Vec<ESpec>, ESpecError> {
+        self.espec_strings()?
+            .into_iter()
+            .map(ESpec::from_str)
+            .collect()
+    }
+
+    /// Checks every page of both key tables against the checksum in its index
+    /// Returns an error for each corrupt page
+    fn verify_pages(&self) -> Vec<EncodingError> {
+        let ce_pages = (0..self.page_count(KeyTable::CKey)).map(|page| (KeyTable::CKey, page));
+        let e_pages = (0..self.page_count(KeyTable::EKey)).map(|page| (KeyTable::EKey, page));
+        ce_pages
+            .chain(e_pages)
+            .filter(|&(table, page)| !matches_checksum(self, table, page))
+            .map(|(table, page)| EncodingError::PageChecksum { table, page })
+            .collect()
+    }
+
+    /// Decodes every entry of the CKey pages, one page at a time
+    fn ce_key_table_entries(
+        &self,
+    ) -> impl Iterator<Item = Result<CeKeyPageEntry, EncodingError>> + '_ {
+        key_table_entries(self, KeyTable::CKey)
+    }
+
+    /// Decodes every entry of the EKey spec pages, one page at a time
+    fn e_key_table_entries(
+        &self,
+    ) -> impl Iterator<Item = Result<EKeySpecPageEntry, EncodingError>> + '_ {
+        key_table_entries(self, KeyTable::EKey)
+    }
+
+    /// Looks up the entry for `c_key`, decoding only the page which may hold it
+    fn find_ckey(&self, c_key: &Key) -> Result<Option<CeKeyPageEntry>, EncodingError> {
+        let table = KeyTable::CKey;
+        let first_key = |page: usize| self.first_key(table, page);
+        let Some(page) = page_for_key(self.page_count(table), first_key, c_key.as_bytes()) else {
+            return Ok(None);
+        };
+        Ok(decode_page::<_, CeKeyPageEntry>(self, table, page)?
+            .into_iter()
+            .find(|entry| entry.c_key.matches(c_key)))
+    }
+
+    /// The EKey spec entry for `e_key`, holding its espec index and encoded size
+    /// Decodes only the page which may hold it
+    fn find_ekey_spec(&self, e_key: &Key) -> Result<Option<EKeySpecPageEntry>, EncodingError> {
+        let table = KeyTable::EKey;
+        let first_key = |page: usize| self.first_key(table, page);
+        let Some(page) = page_for_key(self.page_count(table), first_key, e_key.as_bytes()) else {
+            return Ok(None);
+        };
+        Ok(decode_page::<_, EKeySpecPageEntry>(self, table, page)?
+            .into_iter()
+            .find(|entry| entry.e_key.matches(e_key)))
+    }
+
+    /// The espec `e_key` was encoded with, None if `e_key` is not in the manifest
+    fn espec_for_ekey(&self, e_key: &Key) -> Result<Option<ESpec>, EncodingError> {
+        let Some(entry) = self.find_ekey_spec(e_key)? else {
+            return Ok(None);
+        };
+        let espec = self
+            .espec_strings()?
+            .get(entry.espec_index as usize)
+            .copied()
+            .ok_or(ESpecError {
+                pos: 0,
+                message: "espec index out of range",
+            })?;
+        Ok(Some(ESpec::from_str(espec)?))
+    }
+}
+
+fn matches_checksum<E: EncodingTables + ?Sized>(tables: &E, table: KeyTable, page: usize) -> bool {
+    tables
+        .page(table, page)
+        .is_some_and(|data| page_matches_checksum(data, tables.checksum(table, page)))
+}
+
+/// Verifies and decodes the entries of a page
+/// Corrupt pages are an error or skipped according to the page verification
+fn decode_page<E: EncodingTables + ?Sized, T: KeyPageEntry>(
+    tables: &E,
+    table: KeyTable,
+    page: usize,
+) -> Result<Vec<T>, EncodingError> {
+    if !matches_checksum(tables, table, page) {
+        return tables.page_verification().corrupt_page(table, page);
+    }
+    Ok(tables
+        .page(table, page)
+        .map(|page| decode_key_page(page, tables.key_sizes()))
+        .unwrap_or_default())
+}
+
+/// Decodes every entry of the pages of `table`, one page at a time
+fn key_table_entries<E: EncodingTables + ?Sized, T: KeyPageEntry + 'static>(
+    tables: &E,
+    table: KeyTable,
+) -> impl Iterator<Item = Result<T, EncodingError>> + '_ {
+    (0..tables.page_count(table)).flat_map(move |page| match decode_page(tables, table, page) {
+        Ok(entries) => entries.into_iter().map(Ok).collect::<Vec<_>>(),
+        Err(e) => vec![Err(e)],
+    })
+}
+
+/// Encoding manifest memory-mapped from disk
+#[cfg(feature = "mmap")]
+pub struct MappedEncoding {
+    mmap: memmap2::Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedEncoding {
+    /// Maps the decoded encoding manifest at `path`
+    pub fn open(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: the mapping is read only, the file must not be modified while mapped
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(Self { mmap })
+    }
+
+    /// Zero-copy view over the mapped manifest
+    pub fn view(&self) -> Result<EncodingView<'_>, EncodingError> {
+        EncodingView::new(&self.mmap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use md5::{Digest, Md5};
+
+    use super::*;
+    use crate::EncodingManifest;
+
+    const PAGE_SIZE: usize = 1024;
+
+    fn test_key(index: u32, fill: u8) -> [u8; 16] {
+        let mut key = [fill; 16];
+        key[..4].copy_from_slice(&index.to_be_bytes());
+        key
+    }
+
+    /// Splits entries into zero padded pages, along with the first key of each page
+    fn paginate(entries: Vec<(Vec<u8>, Vec<u8>)>) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+        let (mut pages, mut first_keys) = (Vec::new(), Vec::new());
+        let mut page: Vec<u8> = Vec::new();
+        for (first_key, entry) in entries {
+            if page.len() + entry.len() > PAGE_SIZE {
+                page.resize(PAGE_SIZE, 0);
+                pages.push(std::mem::take(&mut page));
+            }
+            if page.is_empty() {
+                first_keys.push(first_key);
+            }
+            page.extend_from_slice(&entry);
+        }
+        page.resize(PAGE_SIZE, 0);
+        pages.push(page);
+        (pages, first_keys)
+    }
+
+    /// Encoding manifest of `count` files, with `ekey_size` byte encoding keys
+    fn build_manifest(count: u32, ekey_size: usize) -> Vec<u8> {
+        let espec_block = b"z\0b:{256K*=z:9}\0n\0";
+        let (ce_pages, ce_first_keys) = paginate(
+            (0..count)
+                .map(|i| {
+                    let mut entry = vec![1];
+                    entry.extend_from_slice(&(u64::from(i) * 10).to_be_bytes()[3..]);
+                    entry.extend_from_slice(&test_key(i, 0xCC));
+                    entry.extend_from_slice(&test_key(i, 0xEE)[..ekey_size]);
+                    (test_key(i, 0xCC).to_vec(), entry)
+                })
+                .collect(),
+        );
+        let (e_pages, e_first_keys) = paginate(
+            (0..count)
+                .map(|i| {
+                    let mut entry = test_key(i, 0xEE)[..ekey_size].to_vec();
+                    entry.extend_from_slice(&(i % 3).to_be_bytes());
+                    entry.extend_from_slice(&(u64::from(i) * 7).to_be_bytes()[3..]);
+                    (test_key(i, 0xEE)[..ekey_size].to_vec(), entry)
+                })
+                .collect(),
+        );
+
+        let mut data = b"EN".to_vec();
+        data.extend_from_slice(&[1, 16, ekey_size as u8]);
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&(ce_pages.len() as u32).to_be_bytes());
+        data.extend_from_slice(&(e_pages.len() as u32).to_be_bytes());
+        data.push(0);
+        data.extend_from_slice(&(espec_block.len() as u32).to_be_bytes());
+        data.extend_from_slice(espec_block);
+        for (pages, first_keys) in [(ce_pages, ce_first_keys), (e_pages, e_first_keys)] {
+            for (first_key, page) in first_keys.iter().zip(&pages) {
+                data.extend_from_slice(first_key);
+                data.extend_from_slice(&Md5::digest(page));
+            }
+            data.extend(pages.concat());
+        }
+        data
+    }
+
+    /// Checks both implementations of [`EncodingTables`] against the same manifest
+    fn check_lookups(tables: &impl EncodingTables, count: u32, ekey_size: usize) {
+        assert!(tables.verify_pages().is_empty());
+        assert_eq!(tables.ce_key_table_entries().count(), count as usize);
+        assert_eq!(tables.e_key_table_entries().count(), count as usize);
+
+        for i in [0, 1, count / 2, count - 1] {
+            let c_key = Key::from_slice(&test_key(i, 0xCC)).unwrap();
+            let entry = tables.find_ckey(&c_key).unwrap().unwrap();
+            assert_eq!(entry.file_size, u64::from(i) * 10);
+            assert_eq!(entry.e_keys[0].as_bytes(), &test_key(i, 0xEE)[..ekey_size]);
+
+            let e_key = Key::from_slice(&test_key(i, 0xEE)).unwrap();
+            let spec = tables.find_ekey_spec(&e_key).unwrap().unwrap();
+            assert_eq!(spec.espec_index, i % 3);
+            let espec = tables.espec_for_ekey(&e_key).unwrap().unwrap();
+            assert_eq!(espec, tables.especs().unwrap()[(i % 3) as usize]);
+        }
+        let missing = Key::from_slice(&test_key(count, 0xCC)).unwrap();
+        assert!(tables.find_ckey(&missing).unwrap().is_none());
+    }
+
+    #[test]
+    fn view_and_manifest_share_lookups() {
+        for ekey_size in [16, 9] {
+            let data = build_manifest(500, ekey_size);
+            let view = EncodingView::new(&data).unwrap();
+            assert!(view.page_count(KeyTable::CKey) > 1);
+            check_lookups(&view, 500, ekey_size);
+
+            let manifest = EncodingManifest::read(&mut Cursor::new(&data)).unwrap();
+            check_lookups(&manifest, 500, ekey_size);
+        }
+    }
+
+    #[test]
+    fn finds_ekeys_of_any_size() {
+        for ekey_size in [16, 9] {
+            let data = build_manifest(500, ekey_size);
+            let manifest = EncodingManifest::read(&mut Cursor::new(&data)).unwrap();
+            for key_size in [16, 9, 4] {
+                let e_key = Key::from_slice(&test_key(321, 0xEE)[..key_size]).unwrap();
+                let entry = manifest.find_ekey(&e_key).unwrap().unwrap();
+                assert_eq!(entry.c_key.as_bytes(), test_key(321, 0xCC));
+                assert_eq!(entry.file_size, 3210);
+            }
+        }
+
+        // differs from a manifest key past the prefix the reverse index is keyed by
+        let manifest = EncodingManifest::read(&mut Cursor::new(build_manifest(500, 16))).unwrap();
+        let mut other = test_key(321, 0xEE);
+        other[12] = 0;
+        let other = Key::from_slice(&other).unwrap();
+        assert!(manifest.find_ekey(&other).unwrap().is_none());
+    }
+
+    #[test]
+    fn detects_corrupt_pages() {
+        let mut data = build_manifest(100, 16);
+        let last = data.len() - 1;
+        data[last] ^= 0xFF;
+
+        let mut view = EncodingView::new(&data).unwrap();
+        let corrupt_page = view.page_count(KeyTable::EKey) - 1;
+        assert!(matches!(
+            view.verify_pages()[..],
+            [EncodingError::PageChecksum { table: KeyTable::EKey, page }] if page == corrupt_page
+        ));
+        assert_eq!(view.e_key_table_entries().filter(Result::is_err).count(), 1);
+
+        view.page_verification = PageVerification::Skip;
+        assert!(view.e_key_table_entries().all(|entry| entry.is_ok()));
+    }
+
+    #[test]
+    fn rejects_truncated_manifests() {
+        let data = build_manifest(100, 16);
+        let truncated = &data[..data.len() - 1];
+        assert!(matches!(
+            EncodingView::new(truncated),
+            Err(EncodingError::Truncated { .. })
+        ));
+        assert!(EncodingManifest::read(&mut Cursor::new(truncated)).is_err());
+    }
+}
@@ -0,0 +1,348 @@
+use std::fmt;
+
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("invalid espec at offset {pos}, {message}")]
+pub struct ESpecError {
+    pub pos: usize,
+    pub message: &'static str,
+}
+
+/// Encoding specification, describing how a file is BLTE encoded
+/// e.g. "b:{256K*=z:9}"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ESpec {
+    /// 'n', stored as plain data
+    None,
+    /// 'z', zlib compressed with an optional level and window size
+    Zlib {
+        level: Option<u8>,
+        bits: Option<ZlibBits>,
+    },
+    /// 'b', split into blocks each encoded with their own spec
+    Blocks(Vec<BlockSpec>),
+    /// 'e', encrypted with the named TACT key and iv, then encoded with `spec`
+    Encrypted {
+        key_name: u64,
+        iv: Vec<u8>,
+        spec: Box<ESpec>,
+    },
+    /// 'c', BCPack compressed
+    BcPack { bcn: Option<u8> },
+    /// 'g', GDeflate compressed
+    GDeflate { level: Option<u8> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZlibBits {
+    /// Window size in bits
+    Bits(u8),
+    /// MPQ style compression
+    Mpq,
+}
+
+/// One entry of a 'b' block list, e.g. "256K*=z"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockSpec {
+    pub size: BlockSize,
+    pub spec: ESpec,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockSize {
+    /// A single block of this many bytes
+    Single(u64),
+    /// `count` consecutive blocks of `size` bytes,
+    /// as many as needed to cover the remaining data without a count
+    Repeated { size: u64, count: Option<u32> },
+    /// A single block covering the remaining data, '*'
+    Remaining,
+}
+
+impl std::str::FromStr for ESpec {
+    type Err = ESpecError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = ESpecParser { input: s, pos: 0 };
+        let espec = parser.espec()?;
+        if parser.pos != s.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(espec)
+    }
+}
+
+/// Recursive descent parser over an espec string
+struct ESpecParser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl ESpecParser<'_> {
+    fn error(&self, message: &'static str) -> ESpecError {
+        ESpecError {
+            pos: self.pos,
+            message,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.pos).copied()
+    }
+
+    fn eat(&mut self, expected: u8) -> bool {
+        if self.peek() == Some(expected) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: u8, message: &'static str) -> Result<(), ESpecError> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            Err(self.error(message))
+        }
+    }
+
+    fn number(&mut self) -> Result<u64, ESpecError> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.input[start..self.pos].parse().map_err(|_| ESpecError {
+            pos: start,
+            message: "expected a number",
+        })
+    }
+
+    fn small_number(&mut self) -> Result<u8, ESpecError> {
+        let start = self.pos;
+        u8::try_from(self.number()?).map_err(|_| ESpecError {
+            pos: start,
+            message: "number out of range",
+        })
+    }
+
+    fn espec(&mut self) -> Result<ESpec, ESpecError> {
+        let mode = self
+            .peek()
+            .ok_or_else(|| self.error("expected an encoding mode"))?;
+        self.pos += 1;
+        match mode {
+            b'n' => Ok(ESpec::None),
+            b'z' => self.zlib(),
+            b'b' => self.blocks(),
+            b'e' => self.encrypted(),
+            b'c' => Ok(ESpec::BcPack {
+                bcn: self.optional_parameter()?,
+            }),
+            b'g' => Ok(ESpec::GDeflate {
+                level: self.optional_parameter()?,
+            }),
+            _ => {
+                self.pos -= 1;
+                Err(self.error("unknown encoding mode"))
+            }
+        }
+    }
+
+    fn zlib(&mut self) -> Result<ESpec, ESpecError> {
+        if !self.eat(b':') {
+            return Ok(ESpec::Zlib {
+                level: None,
+                bits: None,
+            });
+        }
+        if !self.eat(b'{') {
+            let level = self.small_number()?;
+            return Ok(ESpec::Zlib {
+                level: Some(level),
+                bits: None,
+            });
+        }
+
+        let level = self.small_number()?;
+        self.expect(b',', "expected ',' after zlib level")?;
+        let bits = if self.input[self.pos..].starts_with("mpq") {
+            self.pos += 3;
+            ZlibBits::Mpq
+        } else {
+            ZlibBits::Bits(self.small_number()?)
+        };
+        self.expect(b'}', "expected '}' closing zlib parameters")?;
+        Ok(ESpec::Zlib {
+            level: Some(level),
+            bits: Some(bits),
+        })
+    }
+
+    /// Parses an optional ":n" or ":{n}" parameter
+    fn optional_parameter(&mut self) -> Result<Option<u8>, ESpecError> {
+        if !self.eat(b':') {
+            return Ok(None);
+        }
+        if !self.eat(b'{') {
+            return Ok(Some(self.small_number()?));
+        }
+        let value = self.small_number()?;
+        self.expect(b'}', "expected '}' closing parameter")?;
+        Ok(Some(value))
+    }
+
+    fn hex(&mut self) -> Result<&str, ESpecError> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(self.error("expected hexadecimal digits"));
+        }
+        Ok(&self.input[start..self.pos])
+    }
+
+    fn encrypted(&mut self) -> Result<ESpec, ESpecError> {
+        self.expect(b':', "expected ':' after 'e'")?;
+        self.expect(b'{', "expected '{' opening encryption parameters")?;
+
+        let start = self.pos;
+        let key_name = u64::from_str_radix(self.hex()?, 16).map_err(|_| ESpecError {
+            pos: start,
+            message: "invalid key name",
+        })?;
+        self.expect(b',', "expected ',' after key name")?;
+
+        let start = self.pos;
+        let iv = hex::decode(self.hex()?).map_err(|_| ESpecError {
+            pos: start,
+            message: "invalid iv",
+        })?;
+        self.expect(b',', "expected ',' after iv")?;
+
+        let spec = Box::new(self.espec()?);
+        self.expect(b'}', "expected '}' closing encryption parameters")?;
+        Ok(ESpec::Encrypted { key_name, iv, spec })
+    }
+
+    fn blocks(&mut self) -> Result<ESpec, ESpecError> {
+        self.expect(b':', "expected ':' after 'b'")?;
+        if !self.eat(b'{') {
+            return Ok(ESpec::Blocks(vec![self.block()?]));
+        }
+
+        let mut blocks = vec![self.block()?];
+        while self.eat(b',') {
+            blocks.push(self.block()?);
+        }
+        self.expect(b'}', "expected '}' closing block list")?;
+        Ok(ESpec::Blocks(blocks))
+    }
+
+    fn block(&mut self) -> Result<BlockSpec, ESpecError> {
+        let size = if self.eat(b'*') {
+            BlockSize::Remaining
+        } else {
+            let start = self.pos;
+            let mut size = self.number()?;
+            let unit = if self.eat(b'K') {
+                1024
+            } else if self.eat(b'M') {
+                1024 * 1024
+            } else {
+                1
+            };
+            size = size.checked_mul(unit).ok_or(ESpecError {
+                pos: start,
+                message: "block size out of range",
+            })?;
+
+            if self.eat(b'*') {
+                let count = match self.peek() {
+                    Some(c) if c.is_ascii_digit() => {
+                        let start = self.pos;
+                        Some(u32::try_from(self.number()?).map_err(|_| ESpecError {
+                            pos: start,
+                            message: "block count out of range",
+                        })?)
+                    }
+                    _ => None,
+                };
+                BlockSize::Repeated { size, count }
+            } else {
+                BlockSize::Single(size)
+            }
+        };
+
+        self.expect(b'=', "expected '=' after block size")?;
+        let spec = self.espec()?;
+        Ok(BlockSpec { size, spec })
+    }
+}
+
+impl fmt::Display for ESpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ESpec::None => write!(f, "n"),
+            ESpec::Zlib { level: None, .. } => write!(f, "z"),
+            ESpec::Zlib {
+                level: Some(level),
+                bits: None,
+            } => write!(f, "z:{level}"),
+            ESpec::Zlib {
+                level: Some(level),
+                bits: Some(ZlibBits::Bits(bits)),
+            } => write!(f, "z:{{{level},{bits}}}"),
+            ESpec::Zlib {
+                level: Some(level),
+                bits: Some(ZlibBits::Mpq),
+            } => write!(f, "z:{{{level},mpq}}"),
+            ESpec::Blocks(blocks) => {
+                write!(f, "b:{{")?;
+                for (i, block) in blocks.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{block}")?;
+                }
+                write!(f, "}}")
+            }
+            ESpec::Encrypted { key_name, iv, spec } => {
+                write!(f, "e:{{{key_name:016X},{},{spec}}}", hex::encode_upper(iv))
+            }
+            ESpec::BcPack { bcn: None } => write!(f, "c"),
+            ESpec::BcPack { bcn: Some(bcn) } => write!(f, "c:{{{bcn}}}"),
+            ESpec::GDeflate { level: None } => write!(f, "g"),
+            ESpec::GDeflate { level: Some(level) } => write!(f, "g:{{{level}}}"),
+        }
+    }
+}
+
+impl fmt::Display for BlockSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.size {
+            BlockSize::Single(size) => write_size(f, size)?,
+            BlockSize::Repeated { size, count } => {
+                write_size(f, size)?;
+                write!(f, "*")?;
+                if let Some(count) = count {
+                    write!(f, "{count}")?;
+                }
+            }
+            BlockSize::Remaining => write!(f, "*")?,
+        }
+        write!(f, "={}", self.spec)
+    }
+}
+
+fn write_size(f: &mut fmt::Formatter<'_>, size: u64) -> fmt::Result {
+    if size != 0 && size.is_multiple_of(1024 * 1024) {
+        write!(f, "{}M", size / (1024 * 1024))
+    } else if size != 0 && size.is_multiple_of(1024) {
+        write!(f, "{}K", size / 1024)
+    } else {
+        write!(f, "{size}")
+    }
+}
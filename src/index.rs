@@ -0,0 +1,319 @@
+use binrw::io::Cursor;
+use binrw::BinRead;
+use md5::{Digest, Md5};
+use thiserror::Error;
+
+use crate::{uint_parser, Key};
+
+#[derive(Debug, Error)]
+pub enum IndexError {
+    #[error("binread error")]
+    BinRead(#[from] binrw::Error),
+
+    #[error("index footer not found")]
+    MissingFooter,
+
+    #[error("index footer does not match its checksum")]
+    FooterChecksum,
+
+    #[error("index table of contents does not match its checksum")]
+    TocChecksum,
+
+    #[error("index block {0} does not match its checksum")]
+    BlockChecksum(usize),
+
+    #[error("index holds {actual} entries, footer declares {expected}")]
+    ElementCount { expected: u32, actual: usize },
+}
+
+/// Footer at the end of every CDN index
+#[derive(Debug, Clone, BinRead)]
+#[br(little, import(checksum_size: u8))]
+pub struct IndexFooter {
+    /// Truncated MD5 of the table of contents
+    #[br(count = usize::from(checksum_size))]
+    pub toc_hash: Vec<u8>,
+    #[br(assert(version == 1, "unsupported index version {}", version))]
+    pub version: u8,
+    _unknown: [u8; 2],
+    #[br(assert(block_size_kb > 0, "invalid block size"))]
+    pub block_size_kb: u8,
+    #[br(assert(offset_bytes <= 8, "unsupported offset size {}", offset_bytes))]
+    pub offset_bytes: u8,
+    #[br(assert(size_bytes <= 8, "unsupported size size {}", size_bytes))]
+    pub size_bytes: u8,
+    #[br(assert((1..=Key::FULL_SIZE).contains(&key_size), "unsupported key size {}", key_size))]
+    pub key_size: u8,
+    pub checksum_size: u8,
+    pub num_elements: u32,
+    /// Truncated MD5 of the footer from `version` on, with this field zeroed
+    #[br(count = usize::from(checksum_size))]
+    pub footer_checksum: Vec<u8>,
+}
+
+impl IndexFooter {
+    /// Size of a footer with `checksum_size` byte checksums
+    fn size(checksum_size: u8) -> usize {
+        usize::from(checksum_size) * 2 + 12
+    }
+
+    pub fn block_size(&self) -> usize {
+        usize::from(self.block_size_kb) * 1024
+    }
+
+    pub fn entry_size(&self) -> usize {
+        usize::from(self.key_size) + usize::from(self.size_bytes) + usize::from(self.offset_bytes)
+    }
+
+    /// Whether `footer`, the raw bytes this footer was read from, matches `footer_checksum`
+    fn matches_checksum(&self, footer: &[u8]) -> bool {
+        let checksum_size = usize::from(self.checksum_size);
+        let mut hashed = footer[checksum_size..].to_vec();
+        let checksum_start = hashed.len() - checksum_size;
+        hashed[checksum_start..].fill(0);
+        truncated_md5(&hashed, self.checksum_size) == self.footer_checksum
+    }
+}
+
+fn truncated_md5(data: &[u8], size: u8) -> Vec<u8> {
+    Md5::digest(data)[..usize::from(size)].to_vec()
+}
+
+/// Offset width of archive-group indices, an archive index followed by a 4 byte offset
+pub const ARCHIVE_GROUP_OFFSET_BYTES: u8 = 6;
+
+/// Entry of a CDN index, locating an encoded file inside an archive
+#[derive(Debug, Clone, BinRead)]
+#[br(big, import(key_size: u8, size_bytes: u8, offset_bytes: u8))]
+pub struct IndexEntry {
+    #[br(args(key_size))]
+    pub e_key: Key,
+    #[br(parse_with = uint_parser, args(size_bytes))]
+    pub size: u64,
+    /// Index of the archive in the CDN config's archive list, only set in archive-group indices
+    #[br(if(offset_bytes == ARCHIVE_GROUP_OFFSET_BYTES))]
+    pub archive_index: Option<u16>,
+    #[br(parse_with = uint_parser, args(if archive_index.is_some() { 4 } else { offset_bytes }))]
+    pub offset: u64,
+}
+
+/// CDN `.index` file, a footer followed by blocks of entries sorted by encoding key
+#[derive(Debug)]
+pub struct IndexFile {
+    pub footer: IndexFooter,
+    /// Last encoding key of every block
+    pub toc: Vec<Key>,
+    pub index_entries: Vec<IndexEntry>,
+}
+
+impl IndexFile {
+    /// Parses and validates a whole index
+    pub fn parse(data: &[u8]) -> Result<Self, IndexError> {
+        // the checksum size is stored in the footer, at a position depending on itself,
+        // so every size is tried until one yields a footer matching its own checksum
+        let mut error = IndexError::MissingFooter;
+        let mut found = None;
+        for checksum_size in 1..=16u8 {
+            let Some(footer_start) = data.len().checked_sub(IndexFooter::size(checksum_size))
+            else {
+                break;
+            };
+            if data[data.len() - usize::from(checksum_size) - 5] != checksum_size {
+                continue;
+            }
+            let footer_data = &data[footer_start..];
+            match IndexFooter::read_args(&mut Cursor::new(footer_data), (checksum_size,)) {
+                Ok(footer) if footer.matches_checksum(footer_data) => {
+                    found = Some((checksum_size, footer_start, footer));
+                    break;
+                }
+                Ok(_) => error = IndexError::FooterChecksum,
+                Err(e) => {
+                    if matches!(error, IndexError::MissingFooter) {
+                        error = e.into();
+                    }
+                }
+            }
+        }
+        let (checksum_size, footer_start, footer) = found.ok_or(error)?;
+
+        let block_size = footer.block_size();
+        let key_size = usize::from(footer.key_size);
+        let checksum_size = usize::from(checksum_size);
+        let block_count = footer_start / (block_size + key_size + checksum_size);
+        let (blocks, toc) = data[..footer_start].split_at(block_count * block_size);
+        let toc = &toc[..block_count * (key_size + checksum_size)];
+        if truncated_md5(toc, footer.checksum_size) != footer.toc_hash {
+            return Err(IndexError::TocChecksum);
+        }
+
+        let (toc_keys, block_checksums) = toc.split_at(block_count * key_size);
+        let mut index_entries = Vec::with_capacity(footer.num_elements as usize);
+        for (index, block) in blocks.chunks_exact(block_size).enumerate() {
+            let checksum = &block_checksums[index * checksum_size..][..checksum_size];
+            if truncated_md5(block, footer.checksum_size) != checksum {
+                return Err(IndexError::BlockChecksum(index));
+            }
+            index_entries.extend(decode_block(block, &footer)?);
+        }
+
+        if index_entries.len() != footer.num_elements as usize {
+            return Err(IndexError::ElementCount {
+                expected: footer.num_elements,
+                actual: index_entries.len(),
+            });
+        }
+
+        let toc = toc_keys
+            .chunks_exact(key_size)
+            .filter_map(Key::from_slice)
+            .collect();
+        Ok(Self {
+            footer,
+            toc,
+            index_entries,
+        })
+    }
+
+    /// Whether this is an archive-group index, whose entries each name their archive
+    pub fn is_archive_group(&self) -> bool {
+        self.footer.offset_bytes == ARCHIVE_GROUP_OFFSET_BYTES
+    }
+
+    /// Binary searches the entry of `e_key`, comparing only as many bytes as both keys hold
+    pub fn find(&self, e_key: &Key) -> Option<&IndexEntry> {
+        let size = usize::from(self.footer.key_size.min(e_key.size()));
+        let e_key = &e_key.as_bytes()[..size];
+        self.index_entries
+            .binary_search_by(|entry| entry.e_key.as_bytes()[..size].cmp(e_key))
+            .ok()
+            .map(|index| &self.index_entries[index])
+    }
+}
+
+/// Decodes every entry of a block, stopping at its zero padding
+fn decode_block(block: &[u8], footer: &IndexFooter) -> Result<Vec<IndexEntry>, IndexError> {
+    let args = (footer.key_size, footer.size_bytes, footer.offset_bytes);
+    let mut entries = Vec::new();
+    for entry in block.chunks_exact(footer.entry_size()) {
+        let entry = IndexEntry::read_args(&mut Cursor::new(entry), args)?;
+        if entry.e_key.is_null() {
+            break;
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::Md5Hash;
+
+    const BLOCK_SIZE: usize = 4096;
+
+    pub(crate) fn test_key(index: u32) -> [u8; 16] {
+        let mut key = [0x11; 16];
+        key[..4].copy_from_slice(&index.to_be_bytes());
+        key
+    }
+
+    /// Raw index of `count` entries with `key_size` byte keys and `offset_bytes` byte offsets
+    /// Entry `i` is `i + 1` bytes at offset `i * 100`, in archive `i % 3` of archive-group indices
+    pub(crate) fn build_index(count: u32, key_size: usize, offset_bytes: u8) -> Vec<u8> {
+        let entry_size = key_size + 4 + usize::from(offset_bytes);
+        let (mut blocks, mut last_keys) = (Vec::new(), Vec::new());
+        let mut block = Vec::new();
+        for i in 0..count {
+            if block.len() + entry_size > BLOCK_SIZE {
+                block.resize(BLOCK_SIZE, 0);
+                blocks.push(std::mem::take(&mut block));
+                last_keys.push(test_key(i - 1));
+            }
+            block.extend_from_slice(&test_key(i)[..key_size]);
+            block.extend_from_slice(&(i + 1).to_be_bytes());
+            if offset_bytes == ARCHIVE_GROUP_OFFSET_BYTES {
+                block.extend_from_slice(&(i as u16 % 3).to_be_bytes());
+                block.extend_from_slice(&(i * 100).to_be_bytes());
+            } else {
+                let offset = u64::from(i * 100).to_be_bytes();
+                block.extend_from_slice(&offset[8 - usize::from(offset_bytes)..]);
+            }
+        }
+        block.resize(BLOCK_SIZE, 0);
+        blocks.push(block);
+        last_keys.push(test_key(count - 1));
+
+        let mut toc = Vec::new();
+        for key in &last_keys {
+            toc.extend_from_slice(&key[..key_size]);
+        }
+        for block in &blocks {
+            toc.extend_from_slice(&Md5::digest(block)[..8]);
+        }
+        let mut data = blocks.concat();
+        data.extend_from_slice(&toc);
+        data.extend_from_slice(&Md5::digest(&toc)[..8]);
+        let mut footer = vec![1, 0, 0, 4, offset_bytes, 4, key_size as u8, 8];
+        footer.extend_from_slice(&count.to_le_bytes());
+        footer.extend_from_slice(&[0; 8]);
+        let checksum = Md5::digest(&footer);
+        footer.truncate(12);
+        footer.extend_from_slice(&checksum[..8]);
+        data.extend_from_slice(&footer);
+        data
+    }
+
+    #[test]
+    fn parses_indices_whose_footer_resembles_a_smaller_checksum() {
+        // these element counts put a byte between 1 and 7 where a shorter footer
+        // would store its checksum size
+        for count in [7, 263, 1536, 1791] {
+            let index = IndexFile::parse(&build_index(count, 16, 4)).unwrap();
+            assert_eq!(index.footer.checksum_size, 8);
+            assert_eq!(index.index_entries.len(), count as usize);
+        }
+    }
+
+    #[test]
+    fn parses_indices_of_every_size() {
+        for count in 1..600 {
+            let index = IndexFile::parse(&build_index(count, 16, 4)).unwrap();
+            assert_eq!(index.footer.num_elements, count);
+        }
+    }
+
+    #[test]
+    fn finds_entries() {
+        let index = IndexFile::parse(&build_index(1000, 16, 5)).unwrap();
+        assert_eq!(index.toc.len(), 7);
+        let entry = index.find(&Md5Hash(test_key(789)).into()).unwrap();
+        assert_eq!((entry.size, entry.offset), (790, 78900));
+        assert!(index.find(&Md5Hash(test_key(1000)).into()).is_none());
+    }
+
+    #[test]
+    fn rejects_corrupt_indices() {
+        let data = build_index(100, 16, 4);
+
+        let mut corrupt_block = data.clone();
+        corrupt_block[10] ^= 0xFF;
+        assert!(matches!(
+            IndexFile::parse(&corrupt_block),
+            Err(IndexError::BlockChecksum(0))
+        ));
+
+        let mut corrupt_footer = data.clone();
+        let checksum_start = corrupt_footer.len() - 8;
+        corrupt_footer[checksum_start] ^= 0xFF;
+        assert!(matches!(
+            IndexFile::parse(&corrupt_footer),
+            Err(IndexError::FooterChecksum)
+        ));
+
+        assert!(matches!(
+            IndexFile::parse(&data[..10]),
+            Err(IndexError::MissingFooter)
+        ));
+    }
+}
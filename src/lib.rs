@@ -1,15 +1,30 @@
 #![allow(dead_code)]
 
+use std::collections::HashMap;
 use std::fmt::Write;
+use std::sync::OnceLock;
 
+use binrw::io::Read;
+use binrw::BinRead;
 use binrw::BinResult;
-use binrw::{BinRead, BinReaderExt};
+use md5::{Digest, Md5};
 use thiserror::Error;
 
+use crate::encoding::EncodingTables;
+use crate::espec::ESpecError;
+
+pub mod archive;
 pub mod blte;
 pub mod cdn;
+pub mod crypto;
+pub mod encoding;
+pub mod espec;
+pub mod index;
+pub(crate) mod lz4;
 pub(crate) mod parse;
+pub mod patch;
 pub mod tact;
+pub mod zbsdiff;
 
 #[derive(Debug, Error)]
 #[error("md5 decoding error")]
@@ -21,7 +36,7 @@ impl std::fmt::Debug for Md5Hash {
     }
 }
 
-#[derive(BinRead, PartialEq, Eq, Clone)]
+#[derive(BinRead, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub struct Md5Hash(pub [u8; 16]);
 
 impl Md5Hash {
@@ -50,6 +65,134 @@ impl std::str::FromStr for Md5Hash {
     }
 }
 
+/// Content or encoding key, either the full 16 bytes or truncated as in local storage
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Key {
+    bytes: [u8; 16],
+    size: u8,
+}
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Key {
+    /// Size of an untruncated key
+    pub const FULL_SIZE: u8 = 16;
+
+    /// Size keys are truncated to when indexed for lookups,
+    /// the shortest key size in use so keys of any source share the same prefix
+    pub const INDEX_SIZE: u8 = 9;
+
+    /// Key holding `bytes`, None if there are more than 16 or none at all
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        if bytes.is_empty() || bytes.len() > usize::from(Self::FULL_SIZE) {
+            return None;
+        }
+        let mut key = [0u8; 16];
+        key[..bytes.len()].copy_from_slice(bytes);
+        Some(Self {
+            bytes: key,
+            size: bytes.len() as u8,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..usize::from(self.size)]
+    }
+
+    /// Number of bytes held by the key
+    pub fn size(&self) -> u8 {
+        self.size
+    }
+
+    /// Whether the key holds fewer than 16 bytes
+    pub fn is_truncated(&self) -> bool {
+        self.size < Self::FULL_SIZE
+    }
+
+    /// Whether the key is all zeroes
+    pub fn is_null(&self) -> bool {
+        self.as_bytes().iter().all(|&b| b == 0)
+    }
+
+    /// The key truncated to at most `size` bytes
+    pub fn truncated(&self, size: u8) -> Self {
+        let mut key = self.clone();
+        if size < key.size {
+            key.bytes[usize::from(size)..].fill(0);
+            key.size = size;
+        }
+        key
+    }
+
+    /// Whether both keys are equal over the bytes they share,
+    /// so a truncated key matches the full key it was truncated from
+    ///
+    /// Manifests, indices and configs store keys at different sizes, so every
+    /// lookup in this crate matches keys this way rather than by equality
+    pub fn matches(&self, other: &Key) -> bool {
+        let size = usize::from(self.size.min(other.size));
+        self.bytes[..size] == other.bytes[..size]
+    }
+
+    /// The full key as an MD5 hash, None if the key is truncated
+    pub fn to_md5(&self) -> Option<Md5Hash> {
+        (!self.is_truncated()).then_some(Md5Hash(self.bytes))
+    }
+
+    /// Hexadecimal representation of the key
+    pub fn as_str(&self) -> String {
+        hex::encode(self.as_bytes())
+    }
+}
+
+impl From<Md5Hash> for Key {
+    fn from(hash: Md5Hash) -> Self {
+        Self {
+            bytes: hash.0,
+            size: Self::FULL_SIZE,
+        }
+    }
+}
+
+impl From<&Md5Hash> for Key {
+    fn from(hash: &Md5Hash) -> Self {
+        hash.clone().into()
+    }
+}
+
+impl std::str::FromStr for Key {
+    type Err = Md5Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s).map_err(|_| Md5Error)?;
+        Self::from_slice(&bytes).ok_or(Md5Error)
+    }
+}
+
+impl BinRead for Key {
+    type Args<'a> = (u8,);
+
+    fn read_options<R: binrw::io::Read + binrw::io::Seek>(
+        reader: &mut R,
+        _endian: binrw::Endian,
+        (size,): Self::Args<'_>,
+    ) -> BinResult<Self> {
+        if size == 0 || size > Self::FULL_SIZE {
+            return Err(binrw::Error::AssertFail {
+                pos: reader.stream_position()?,
+                message: format!("unsupported key size {size}"),
+            });
+        }
+        let mut bytes = [0u8; 16];
+        reader.read_exact(&mut bytes[..usize::from(size)])?;
+        Ok(Self { bytes, size })
+    }
+}
+
 #[derive(Debug, BinRead)]
 #[br(big, magic = b"IN")]
 pub struct InstallManifest {
@@ -59,14 +202,16 @@ pub struct InstallManifest {
     pub num_entries: u32,
     #[br(count = usize::from(num_tags), args { inner: (num_entries / 8,) })]
     pub tags: Vec<ManifestTag>,
-    #[br(count = num_entries)]
+    #[br(count = num_entries, args { inner: (encoding_hash_size,) })]
     pub entries: Vec<InstallManifestEntry>,
 }
 
 #[derive(Debug, BinRead)]
+#[br(import(hash_size: u8))]
 pub struct InstallManifestEntry {
     pub name: binrw::NullString,
-    pub hash: Md5Hash,
+    #[br(args(hash_size))]
+    pub hash: Key,
     pub size: u32,
 }
 
@@ -78,19 +223,71 @@ pub struct DownloadManifest {
     pub include_checksum: u8,
     pub num_entries: u32,
     pub num_tags: u16,
-    #[br(count = num_entries)]
+    #[br(count = num_entries, args { inner: (encoding_key_size,) })]
     pub entries: Vec<DownloadManifestEntry>,
     #[br(count = usize::from(num_tags), args { inner: (num_entries / 8,) })]
     pub tags: Vec<ManifestTag>,
 }
 
 #[derive(Debug, BinRead)]
+#[br(import(key_size: u8))]
 pub struct DownloadManifestEntry {
-    pub hash: Md5Hash,
-    pub file_size: [u8; 5],
+    #[br(args(key_size))]
+    pub hash: Key,
+    #[br(parse_with = u40_parser)]
+    pub file_size: u64,
     pub priority: u8,
 }
 
+/// Parses a big endian 40 bit integer, as used for file sizes
+#[binrw::parser(reader)]
+fn u40_parser() -> BinResult<u64> {
+    let bytes = <[u8; 5]>::read_options(reader, binrw::Endian::Big, ())?;
+    Ok(bytes
+        .iter()
+        .fold(0u64, |size, &byte| size << 8 | u64::from(byte)))
+}
+
+/// Size manifest, the estimated encoded size of every file
+#[derive(Debug, BinRead)]
+#[br(big, magic = b"DS")]
+pub struct SizeManifest {
+    #[br(assert(version == 1 || version == 2, "unsupported size manifest version {}", version))]
+    pub version: u8,
+    pub ekey_size: u8,
+    pub num_entries: u32,
+    pub num_tags: u16,
+    /// Sum of every entry's estimated size
+    #[br(parse_with = uint_parser, args(if version == 1 { 8 } else { 5 }))]
+    pub total_size: u64,
+    #[br(if(version == 1, 4), assert(esize_bytes <= 8, "unsupported esize size {}", esize_bytes))]
+    pub esize_bytes: u8,
+    #[br(count = usize::from(num_tags), args { inner: (num_entries.div_ceil(8),) })]
+    pub tags: Vec<ManifestTag>,
+    #[br(count = num_entries, args { inner: (ekey_size, esize_bytes) })]
+    pub entries: Vec<SizeManifestEntry>,
+}
+
+#[derive(Debug, BinRead)]
+#[br(import(ekey_size: u8, esize_bytes: u8))]
+pub struct SizeManifestEntry {
+    #[br(args(ekey_size))]
+    pub key: Key,
+    pub key_hash: u16,
+    /// Estimated encoded size
+    #[br(parse_with = uint_parser, args(esize_bytes))]
+    pub esize: u64,
+}
+
+/// Parses a big endian unsigned integer `width` bytes wide
+#[binrw::parser(reader)]
+fn uint_parser(width: u8) -> BinResult<u64> {
+    let mut bytes = [0u8; 8];
+    let start = bytes.len() - usize::from(width);
+    reader.read_exact(&mut bytes[start..])?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
 #[derive(Debug, BinRead)]
 #[br(import(mask_len: u32))]
 pub struct ManifestTag {
@@ -100,14 +297,24 @@ pub struct ManifestTag {
     pub mask: Vec<u8>,
 }
 
+/// Entry of an encoding manifest key page, pages are zero padded
+/// Read with the manifest's ckey and ekey sizes
+trait KeyPageEntry: for<'a> BinRead<Args<'a> = (u8, u8)> {
+    /// Whether this entry was read from the padding at the end of a page
+    fn is_padding(&self) -> bool;
+}
+
 #[binrw::parser(reader)]
-fn key_table_data_parser(page_size_kb: u16, num_pages: u32) -> BinResult<Vec<CeKeyPageEntry>> {
-    let mut results = Vec::new();
+fn key_table_pages_parser(page_size_kb: u16, num_pages: u32) -> BinResult<KeyTablePages> {
+    let page_size = page_size_kb as usize * 1024;
+    // the page count comes from the header, so the buffer only grows as pages are actually read
+    let mut data = Vec::new();
     let mut page_count = 0;
     while page_count != num_pages as usize {
-        let mut page = vec![0u8; page_size_kb as usize * 1024];
-        let read_n = reader.read(&mut page)?;
-        if read_n != page_size_kb as usize * 1024 {
+        let read = (&mut *reader)
+            .take(page_size as u64)
+            .read_to_end(&mut data)?;
+        if read != page_size {
             return Err(binrw::Error::AssertFail {
                 pos: reader.stream_position()?,
                 message: format!(
@@ -116,16 +323,133 @@ fn key_table_data_parser(page_size_kb: u16, num_pages: u32) -> BinResult<Vec<CeK
                 ),
             });
         }
+        page_count += 1;
+    }
 
-        let mut cursor = binrw::io::Cursor::new(page);
-        while let Ok(entry) = cursor.read_be::<CeKeyPageEntry>() {
-            results.push(entry);
+    Ok(KeyTablePages { page_size, data })
+}
+
+/// Raw fixed size pages of an encoding manifest key table, decoded on demand
+#[derive(Debug)]
+pub struct KeyTablePages {
+    page_size: usize,
+    data: Vec<u8>,
+}
+
+impl KeyTablePages {
+    pub fn page_count(&self) -> usize {
+        self.data.len().checked_div(self.page_size).unwrap_or(0)
+    }
+
+    /// Raw bytes of the page at `index`
+    pub fn page(&self, index: usize) -> Option<&[u8]> {
+        self.data
+            .get(index * self.page_size..(index + 1) * self.page_size)
+    }
+}
+
+/// Decodes the entries of a single key page, stopping at its padding
+fn decode_key_page<T: KeyPageEntry>(page: &[u8], key_sizes: (u8, u8)) -> Vec<T> {
+    let mut results = Vec::new();
+    let mut cursor = binrw::io::Cursor::new(page);
+    while let Ok(entry) = T::read_options(&mut cursor, binrw::Endian::Big, key_sizes) {
+        if entry.is_padding() {
+            break;
         }
+        results.push(entry);
+    }
+    results
+}
 
-        page_count += 1;
+/// Index of the page which may hold `key`, binary searching the first key of every page
+fn page_for_key<'k>(
+    page_count: usize,
+    first_key: impl Fn(usize) -> &'k [u8],
+    key: &[u8],
+) -> Option<usize> {
+    // pages are sorted, so the key can only be in the last page starting at or before it
+    // keys are compared over the bytes they share, in case either is truncated
+    let (mut low, mut high) = (0, page_count);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let first = first_key(mid);
+        let size = first.len().min(key.len());
+        if first[..size] <= key[..size] {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low.checked_sub(1)
+}
+
+/// Whether the MD5 of `page` matches `checksum`
+fn page_matches_checksum(page: &[u8], checksum: &[u8]) -> bool {
+    Md5::digest(page).as_slice() == checksum
+}
+
+/// Splits an espec block into its null terminated strings
+fn split_espec_block(espec_block: &[u8]) -> Result<Vec<&str>, ESpecError> {
+    let mut strings = Vec::new();
+    let mut offset = 0;
+    for string in espec_block.split(|&b| b == 0) {
+        if !string.is_empty() {
+            let string = std::str::from_utf8(string).map_err(|e| ESpecError {
+                pos: offset + e.valid_up_to(),
+                message: "espec block is not valid utf8",
+            })?;
+            strings.push(string);
+        }
+        offset += string.len() + 1;
+    }
+    Ok(strings)
+}
+
+/// Key tables of an encoding manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyTable {
+    /// CKey to EKey pages
+    CKey,
+    /// EKey to ESpec pages
+    EKey,
+}
+
+/// How pages failing their checksum are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PageVerification {
+    /// Fail with [`EncodingError::PageChecksum`]
+    #[default]
+    Strict,
+    /// Skip the page as though it held no entries
+    Skip,
+}
+
+impl PageVerification {
+    /// Result of decoding a page which failed its checksum
+    fn corrupt_page<T>(self, table: KeyTable, page: usize) -> Result<Vec<T>, EncodingError> {
+        match self {
+            PageVerification::Strict => Err(EncodingError::PageChecksum { table, page }),
+            PageVerification::Skip => {
+                tracing::warn!("skipping {table:?} page {page}, checksum mismatch");
+                Ok(Vec::new())
+            }
+        }
     }
+}
 
-    Ok(results)
+#[derive(Debug, Error)]
+pub enum EncodingError {
+    #[error("{table:?} page {page} does not match its checksum")]
+    PageChecksum { table: KeyTable, page: usize },
+
+    #[error("invalid espec")]
+    ESpec(#[from] ESpecError),
+
+    #[error("invalid encoding header")]
+    BinRead(#[from] binrw::Error),
+
+    #[error("encoding manifest truncated, expected {expected} bytes but found {actual}")]
+    Truncated { expected: usize, actual: usize },
 }
 
 #[derive(Debug, BinRead)]
@@ -146,65 +470,233 @@ pub struct EncodingManifest {
     pub espec_block_size: u32,
     #[br(count = espec_block_size)]
     pub espec_block: Vec<u8>,
-    #[br(count = ce_key_table_page_count)]
+    #[br(count = ce_key_table_page_count, args { inner: (ckey_hash_size,) })]
     pub ce_key_table_index: Vec<CeKeyTableIndex>,
-    #[br(parse_with = key_table_data_parser, args (ce_page_size_kb, ce_key_table_page_count, ) )]
-    pub ce_key_table_entries: Vec<CeKeyPageEntry>,
+    #[br(parse_with = key_table_pages_parser, args (ce_page_size_kb, ce_key_table_page_count, ) )]
+    pub ce_key_table_pages: KeyTablePages,
+    #[br(count = e_key_table_count, args { inner: (ekey_hash_size,) })]
+    pub e_key_table_index: Vec<EKeyTableIndex>,
+    #[br(parse_with = key_table_pages_parser, args (e_page_size_kb, e_key_table_count, ) )]
+    pub e_key_table_pages: KeyTablePages,
+
+    /// How pages failing their checksum are handled when decoded
+    #[br(ignore)]
+    pub page_verification: PageVerification,
+
+    #[br(ignore)]
+    ekey_index: OnceLock<HashMap<Key, EKeyIndexEntry>>,
+}
+
+impl EncodingTables for EncodingManifest {
+    fn key_sizes(&self) -> (u8, u8) {
+        (self.ckey_hash_size, self.ekey_hash_size)
+    }
+
+    fn page_count(&self, table: KeyTable) -> usize {
+        match table {
+            KeyTable::CKey => self.ce_key_table_page_count as usize,
+            KeyTable::EKey => self.e_key_table_count as usize,
+        }
+    }
+
+    fn page(&self, table: KeyTable, page: usize) -> Option<&[u8]> {
+        match table {
+            KeyTable::CKey => self.ce_key_table_pages.page(page),
+            KeyTable::EKey => self.e_key_table_pages.page(page),
+        }
+    }
+
+    fn first_key(&self, table: KeyTable, page: usize) -> &[u8] {
+        match table {
+            KeyTable::CKey => self.ce_key_table_index[page].first_key.as_bytes(),
+            KeyTable::EKey => self.e_key_table_index[page].first_key.as_bytes(),
+        }
+    }
+
+    fn checksum(&self, table: KeyTable, page: usize) -> &[u8] {
+        match table {
+            KeyTable::CKey => &self.ce_key_table_index[page].md5.0,
+            KeyTable::EKey => &self.e_key_table_index[page].md5.0,
+        }
+    }
+
+    fn espec_block(&self) -> &[u8] {
+        &self.espec_block
+    }
+
+    fn page_verification(&self) -> PageVerification {
+        self.page_verification
+    }
+}
+
+impl EncodingManifest {
+    /// Reverse index mapping every encoding key to its content key and decoded size
+    /// Keys are truncated to [`Key::INDEX_SIZE`], built from every CKey page on first use
+    pub fn ekey_index(&self) -> Result<&HashMap<Key, EKeyIndexEntry>, EncodingError> {
+        if let Some(ekey_index) = self.ekey_index.get() {
+            return Ok(ekey_index);
+        }
+
+        let mut ekey_index = HashMap::new();
+        for entry in self.ce_key_table_entries() {
+            let entry = entry?;
+            for e_key in entry.e_keys {
+                ekey_index
+                    .entry(e_key.truncated(Key::INDEX_SIZE))
+                    .or_insert_with(|| EKeyIndexEntry {
+                        e_key,
+                        c_key: entry.c_key.clone(),
+                        file_size: entry.file_size,
+                    });
+            }
+        }
+        Ok(self.ekey_index.get_or_init(|| ekey_index))
+    }
+
+    /// Looks up the content key and decoded size of `e_key` through the reverse index
+    pub fn find_ekey(&self, e_key: &Key) -> Result<Option<&EKeyIndexEntry>, EncodingError> {
+        let ekey_index = self.ekey_index()?;
+        let index_size = Key::INDEX_SIZE.min(self.ekey_hash_size);
+        if e_key.size() < index_size {
+            // too short to form an index key, only a scan over every entry can find it
+            return Ok(ekey_index.values().find(|entry| entry.e_key.matches(e_key)));
+        }
+        Ok(ekey_index
+            .get(&e_key.truncated(index_size))
+            .filter(|entry| entry.e_key.matches(e_key)))
+    }
 }
 
 #[derive(Debug, BinRead)]
+#[br(import(key_size: u8))]
 pub struct CeKeyTableIndex {
-    pub first_key: Md5Hash,
+    #[br(args(key_size))]
+    pub first_key: Key,
     pub md5: Md5Hash,
 }
 
 #[derive(Debug, BinRead)]
+#[br(import(ckey_size: u8, ekey_size: u8))]
 pub struct CeKeyPageEntry {
     pub key_count: u8,
-    pub file_size: [u8; 5],
-    pub c_key: Md5Hash,
-    #[br(count = usize::from(key_count))]
-    pub e_keys: Vec<Md5Hash>,
+    #[br(parse_with = u40_parser)]
+    pub file_size: u64,
+    #[br(args(ckey_size))]
+    pub c_key: Key,
+    #[br(count = usize::from(key_count), args { inner: (ekey_size,) })]
+    pub e_keys: Vec<Key>,
+}
+
+impl KeyPageEntry for CeKeyPageEntry {
+    fn is_padding(&self) -> bool {
+        self.key_count == 0
+    }
+}
+
+/// Entry of the reverse index built by [`EncodingManifest::ekey_index`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EKeyIndexEntry {
+    /// Encoding key as stored in the manifest
+    pub e_key: Key,
+    pub c_key: Key,
+    pub file_size: u64,
 }
 
 #[derive(Debug, BinRead)]
-pub struct IndexEntry {
-    pub e_key: Md5Hash,
-    pub size: u32,
-    pub offset: u32,
+#[br(import(key_size: u8))]
+pub struct EKeyTableIndex {
+    #[br(args(key_size))]
+    pub first_key: Key,
+    pub md5: Md5Hash,
 }
 
 #[derive(Debug, BinRead)]
-pub struct IndexFile {
-    #[br(parse_with = index_table_data_parser)]
-    pub index_entries: Vec<IndexEntry>,
+#[br(import(_ckey_size: u8, ekey_size: u8))]
+pub struct EKeySpecPageEntry {
+    #[br(args(ekey_size))]
+    pub e_key: Key,
+    /// Index of the espec in the espec block
+    pub espec_index: u32,
+    #[br(parse_with = u40_parser)]
+    pub file_size: u64,
 }
 
-#[binrw::parser(reader)]
-fn index_table_data_parser() -> BinResult<Vec<IndexEntry>> {
-    let mut results = Vec::new();
+impl KeyPageEntry for EKeySpecPageEntry {
+    fn is_padding(&self) -> bool {
+        self.e_key.is_null()
+    }
+}
 
-    loop {
-        let mut page = vec![0u8; 4096];
-        let read_n = reader.read(&mut page)?;
-        if read_n != 4096 {
-            return Ok(results);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY_COUNT: u32 = 10;
+
+    /// Size manifest of `ENTRY_COUNT` files with 9 byte keys, whose esizes are `esize_bytes` wide
+    fn build_size_manifest(version: u8, esize_bytes: u8) -> Vec<u8> {
+        let mut data = b"DS".to_vec();
+        data.extend_from_slice(&[version, 9]);
+        data.extend_from_slice(&ENTRY_COUNT.to_be_bytes());
+        data.extend_from_slice(&1u16.to_be_bytes());
+        let total_size: u64 = (0..u64::from(ENTRY_COUNT)).map(|i| i * 1000).sum();
+        if version == 1 {
+            data.extend_from_slice(&total_size.to_be_bytes());
+            data.push(esize_bytes);
+        } else {
+            data.extend_from_slice(&total_size.to_be_bytes()[3..]);
+        }
+        data.extend_from_slice(b"Windows\0");
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&[0xFF, 0xC0]);
+        for i in 0..ENTRY_COUNT {
+            data.extend_from_slice(&[i as u8; 9]);
+            data.extend_from_slice(&(i as u16).to_be_bytes());
+            data.extend_from_slice(
+                &(u64::from(i) * 1000).to_be_bytes()[8 - usize::from(esize_bytes)..],
+            );
         }
-        let mut cursor = binrw::io::Cursor::new(page);
+        data
+    }
 
-        match cursor.read_be::<IndexEntry>() {
-            Ok(entry) => {
-                if entry.e_key.is_null() {
-                    tracing::info!("ending parsing index file, because we encountered a null hash");
-                    return Ok(results);
-                }
+    fn check_size_manifest(manifest: &SizeManifest) {
+        assert_eq!(manifest.num_entries, ENTRY_COUNT);
+        assert_eq!(manifest.total_size, 45000);
+        assert_eq!(manifest.tags.len(), 1);
+        assert_eq!(manifest.tags[0].name.to_string(), "Windows");
+        assert_eq!(manifest.tags[0].mask, [0xFF, 0xC0]);
+        for (i, entry) in manifest.entries.iter().enumerate() {
+            assert_eq!(entry.key.as_bytes(), [i as u8; 9]);
+            assert_eq!(entry.key_hash, i as u16);
+            assert_eq!(entry.esize, i as u64 * 1000);
+        }
+    }
 
-                results.push(entry);
-            }
-            Err(e) => {
-                tracing::warn!("ending with an err... {e:?}");
-                return Ok(results);
-            }
+    #[test]
+    fn parses_size_manifests_v1() {
+        for esize_bytes in [3, 4, 8] {
+            let data = build_size_manifest(1, esize_bytes);
+            let manifest = SizeManifest::read(&mut binrw::io::Cursor::new(data)).unwrap();
+            assert_eq!(manifest.esize_bytes, esize_bytes);
+            check_size_manifest(&manifest);
         }
     }
+
+    #[test]
+    fn parses_size_manifests_v2() {
+        let data = build_size_manifest(2, 4);
+        let manifest = SizeManifest::read(&mut binrw::io::Cursor::new(data)).unwrap();
+        assert_eq!(manifest.esize_bytes, 4);
+        check_size_manifest(&manifest);
+    }
+
+    #[test]
+    fn rejects_unsupported_size_manifests() {
+        let data = build_size_manifest(3, 4);
+        assert!(SizeManifest::read(&mut binrw::io::Cursor::new(data)).is_err());
+        let mut data = build_size_manifest(1, 4);
+        // esize width, following the magic, version, key size, counts and total size
+        data[18] = 9;
+        assert!(SizeManifest::read(&mut binrw::io::Cursor::new(data)).is_err());
+    }
 }
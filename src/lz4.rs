@@ -0,0 +1,69 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[error("invalid lz4 block at input offset {0}")]
+pub struct Lz4Error(pub usize);
+
+/// Decodes a single raw LZ4 block from the start of `input`, appending to `output`
+/// Decoding stops once `block_size` bytes have been produced or the input ends
+/// Returns the number of input bytes consumed
+pub fn decompress_block(
+    input: &[u8],
+    output: &mut Vec<u8>,
+    block_size: usize,
+) -> Result<usize, Lz4Error> {
+    let block_start = output.len();
+    let block_end = block_start + block_size;
+    let mut pos = 0;
+
+    let read_length = |pos: &mut usize, mut length: usize| -> Result<usize, Lz4Error> {
+        if length == 15 {
+            loop {
+                let byte = *input.get(*pos).ok_or(Lz4Error(*pos))?;
+                *pos += 1;
+                length += usize::from(byte);
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(length)
+    };
+
+    while pos < input.len() {
+        let token = input[pos];
+        pos += 1;
+
+        let literal_length = read_length(&mut pos, usize::from(token >> 4))?;
+        let literals = input.get(pos..pos + literal_length).ok_or(Lz4Error(pos))?;
+        if output.len() + literal_length > block_end {
+            return Err(Lz4Error(pos));
+        }
+        output.extend_from_slice(literals);
+        pos += literal_length;
+
+        // the last sequence of a block only carries literals
+        if output.len() >= block_end || pos == input.len() {
+            break;
+        }
+
+        let offset = input.get(pos..pos + 2).ok_or(Lz4Error(pos))?;
+        let offset = usize::from(u16::from_le_bytes([offset[0], offset[1]]));
+        pos += 2;
+        // matches may only reference bytes of the current block
+        if offset == 0 || offset > output.len() - block_start {
+            return Err(Lz4Error(pos));
+        }
+
+        let match_length = read_length(&mut pos, usize::from(token & 0xF))? + 4;
+        if output.len() + match_length > block_end {
+            return Err(Lz4Error(pos));
+        }
+        let match_start = output.len() - offset;
+        for i in 0..match_length {
+            output.push(output[match_start + i]);
+        }
+    }
+
+    Ok(pos)
+}
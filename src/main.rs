@@ -1,16 +1,22 @@
-use std::{
-    io::{Cursor, Write},
-    path::Path,
-};
+use std::{io::Cursor, ops::Range, path::Path};
 
 use binrw::BinRead;
 use blizztools::{
-    blte::BlockTable,
-    cdn::parse_build_config,
-    tact::{parse_cdn_table, parse_version_table},
-    EncodingManifest, InstallManifest, Md5Hash,
+    archive::ArchiveResolver,
+    blte::{AsyncBlteReader, BlteHeader, DecodeOptions},
+    cdn::{parse_build_config, parse_cdn_config, BuildConfig},
+    crypto::TactKeyList,
+    encoding::EncodingTables,
+    index::IndexFile,
+    patch::PatchManifest,
+    tact::{parse_cdn_table, parse_version_table, VersionDefinition},
+    zbsdiff, EncodingManifest, InstallManifest, Md5Hash, SizeManifest,
 };
 use clap::{Args, Parser, Subcommand, ValueEnum};
+use futures_util::{StreamExt, TryStreamExt};
+use md5::{Digest, Md5};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_util::io::StreamReader;
 
 /// All available products
 #[allow(clippy::enum_variant_names)]
@@ -98,8 +104,12 @@ enum Commands {
     Cdn(CdnArgs),
     /// Command that will download the encoding and install manifest for a product
     InstallManifest(ManifestArgs),
+    /// Command that will download the size manifest for a product
+    SizeManifest(ManifestArgs),
     /// Command that will download a selected file from a version's install
     Download(DownloadArgs),
+    /// Command that will upgrade a file downloaded from an older version by applying a patch
+    Upgrade(UpgradeArgs),
 }
 
 /// Get available versions for product
@@ -129,6 +139,31 @@ struct DownloadArgs {
     content_key: Md5Hash,
     /// Destination folder for downloads
     output: std::path::PathBuf,
+    /// TACTKey list used to decrypt encrypted chunks
+    #[arg(long)]
+    keys: Option<std::path::PathBuf>,
+    /// Skip verifying chunk checksums while decoding
+    #[arg(long)]
+    no_verify: bool,
+    /// Offset of the first decoded byte to download, used with --length
+    #[arg(long, default_value_t = 0)]
+    offset: u64,
+    /// Only download and decode this many bytes, fetching just the chunks covering them
+    #[arg(long)]
+    length: Option<u64>,
+}
+
+/// Arguments for cli command to upgrade a file by content key
+#[derive(Debug, Args)]
+struct UpgradeArgs {
+    /// The product you want to upgrade a file of
+    product: Product,
+    /// The content key of the file in the latest version
+    content_key: Md5Hash,
+    /// The file as downloaded from an older version
+    input: std::path::PathBuf,
+    /// Destination folder for upgraded files
+    output: std::path::PathBuf,
 }
 
 #[tokio::main]
@@ -140,7 +175,9 @@ async fn main() -> anyhow::Result<()> {
         Commands::Version(args) => versions_command(args).await?,
         Commands::Cdn(args) => cdn_command(args).await?,
         Commands::InstallManifest(args) => install_manifest_command(args).await?,
+        Commands::SizeManifest(args) => size_manifest_command(args).await?,
         Commands::Download(args) => download_command(args).await?,
+        Commands::Upgrade(args) => upgrade_command(args).await?,
     }
     Ok(())
 }
@@ -171,47 +208,16 @@ async fn versions_command(args: VersionArgs) -> anyhow::Result<()> {
 }
 
 async fn install_manifest_command(args: ManifestArgs) -> anyhow::Result<()> {
-    let url = format!(
-        "http://us.patch.battle.net:1119/{}",
-        &args.product.cdn_path()
-    );
-    let cdn_bytes = reqwest::get(format!("{url}/cdns")).await?.text().await?;
-    let cdn_table = parse_cdn_table(&cdn_bytes)?;
-    tracing::debug!("{cdn_table:#?}");
-
-    let version_bytes = reqwest::get(format!("{url}/versions"))
-        .await?
-        .text()
-        .await?;
-    let version_table = parse_version_table(&version_bytes)?;
-    tracing::debug!("{version_table:#?}");
-
-    let cdn_definition = cdn_table
-        .into_iter()
-        .next()
-        .ok_or(anyhow::anyhow!("atleast one cdn entry"))?;
-    let selected_server = cdn_definition
-        .servers
-        .into_iter()
-        .filter(|server| server.contains(".cdn"))
-        .next()
-        .ok_or(anyhow::anyhow!("atleast one server entry"))?;
-    let version_definition = version_table
-        .into_iter()
-        .next()
-        .ok_or(anyhow::anyhow!("atleast one version entry"))?;
-
-    tracing::debug!("latest version: {}", &version_definition.version_name);
-    let selected_cdn = format!("{}/{}", selected_server, cdn_definition.path);
-    tracing::trace!("selected cdn: {selected_cdn}");
-
-    let build_config_hash = version_definition.build_config;
-    let build_config = download_config(&selected_cdn, &build_config_hash).await?;
-    let build_config = parse_build_config(&build_config)?;
-    tracing::debug!("{build_config:#?}");
-
-    let install_config_hash = build_config.install.1;
-    let table_data = download_by_ekey(&selected_cdn, &install_config_hash).await?;
+    let (selected_cdn, _, build_config) = latest_build(args.product).await?;
+
+    let (install_c_key, install_e_key) = &build_config.install;
+    let table_data = download_blte(
+        &BlteSource::loose(&selected_cdn, install_e_key),
+        install_c_key,
+        u64::from(build_config.install_size.0),
+        &DecodeOptions::default(),
+    )
+    .await?;
     let install_manifest = InstallManifest::read(&mut Cursor::new(table_data))?;
 
     install_manifest
@@ -222,11 +228,229 @@ async fn install_manifest_command(args: ManifestArgs) -> anyhow::Result<()> {
     Ok(())
 }
 
+async fn size_manifest_command(args: ManifestArgs) -> anyhow::Result<()> {
+    let (selected_cdn, _, build_config) = latest_build(args.product).await?;
+
+    let (size_c_key, size_e_key) = build_config
+        .size
+        .ok_or(anyhow::anyhow!("version has no size manifest"))?;
+    let decoded_size = build_config.size_size.map_or(0, |size| u64::from(size.0));
+    let table_data = download_blte(
+        &BlteSource::loose(&selected_cdn, &size_e_key),
+        &size_c_key,
+        decoded_size,
+        &DecodeOptions::default(),
+    )
+    .await?;
+    let size_manifest = SizeManifest::read(&mut Cursor::new(table_data))?;
+
+    println!("Total Size: {}", size_manifest.total_size);
+    size_manifest
+        .entries
+        .iter()
+        .for_each(|entry| println!("EKey: {:?} , ESize: {}", entry.key, entry.esize));
+    Ok(())
+}
+
 async fn download_command(args: DownloadArgs) -> anyhow::Result<()> {
-    let url = format!(
-        "http://us.patch.battle.net:1119/{}",
-        &args.product.cdn_path()
+    let range = args
+        .length
+        .map(|length| {
+            let end = args.offset.checked_add(length).ok_or(anyhow::anyhow!(
+                "--offset {} plus --length {length} overflows",
+                args.offset
+            ))?;
+            anyhow::Ok(args.offset..end)
+        })
+        .transpose()?;
+
+    let (selected_cdn, version_definition, build_config) = latest_build(args.product).await?;
+    let output_dir = args
+        .output
+        .join(args.product.cdn_path())
+        .join(&version_definition.version_name);
+
+    tracing::debug!("output dir: {output_dir:?}");
+    if !Path::new(&output_dir).exists() {
+        std::fs::create_dir_all(&output_dir)?;
+    }
+
+    let key_list = args.keys.map(TactKeyList::load).transpose()?;
+    let mut decode_options = match &key_list {
+        Some(key_list) => DecodeOptions::with_key_store(key_list),
+        None => DecodeOptions::default(),
+    };
+    decode_options.verify_checksums = !args.no_verify;
+
+    let (encoding_c_key, encoding_e_key) = &build_config.encoding;
+    let table_data = download_blte(
+        &BlteSource::loose(&selected_cdn, encoding_e_key),
+        encoding_c_key,
+        u64::from(build_config.encoding_size.0),
+        &decode_options,
+    )
+    .await?;
+    let encoding_table: EncodingManifest = EncodingManifest::read(&mut Cursor::new(table_data))?;
+
+    tracing::debug!("beginning download of content key: {:?}", args.content_key);
+    let (e_key, file_size) = find_ekey(&encoding_table, &args.content_key)?;
+    tracing::debug!(
+        "content key {:?} decodes to {file_size} bytes",
+        args.content_key
+    );
+
+    let cdn_config = download_config(&selected_cdn, &version_definition.cdn_config).await?;
+    let cdn_config = parse_cdn_config(&cdn_config)?;
+    let archive_resolver = load_archive_indices(
+        &selected_cdn,
+        &cdn_config.archives,
+        cdn_config.archive_group.as_ref(),
+        data_url,
+    )
+    .await?;
+    let source = BlteSource::resolve(&selected_cdn, &e_key, &archive_resolver);
+
+    // written under a temporary name first, so a failed download never takes the content key's name
+    let path = output_dir.join(args.content_key.as_str());
+    let partial_path = path.with_extension("part");
+    let mut output_file = tokio::fs::File::create(&partial_path).await?;
+    let download = async {
+        let size = match range {
+            Some(range) => {
+                let data =
+                    download_blte_range(&source, &args.content_key, range, &decode_options).await?;
+                output_file.write_all(&data).await?;
+                data.len() as u64
+            }
+            None => {
+                output_file.set_len(file_size).await?;
+                download_blte_into(
+                    &source,
+                    &args.content_key,
+                    &decode_options,
+                    &mut output_file,
+                )
+                .await?
+            }
+        };
+        output_file.flush().await?;
+        anyhow::Ok(size)
+    };
+    let result = download.await;
+    drop(output_file);
+    let size = match result {
+        Ok(size) => size,
+        Err(e) => {
+            if let Err(remove_error) = tokio::fs::remove_file(&partial_path).await {
+                tracing::warn!("failed to remove {partial_path:?}: {remove_error}");
+            }
+            return Err(e);
+        }
+    };
+    tokio::fs::rename(&partial_path, &path).await?;
+    tracing::debug!(
+        "successfully downloaded content key: {:?} with size: {}",
+        &args.content_key,
+        size
     );
+    Ok(())
+}
+
+async fn upgrade_command(args: UpgradeArgs) -> anyhow::Result<()> {
+    let (selected_cdn, version_definition, build_config) = latest_build(args.product).await?;
+
+    let patch_manifest_hash = build_config
+        .patch
+        .ok_or(anyhow::anyhow!("version has no patch manifest"))?;
+    let patch_manifest = download_patch(&selected_cdn, &patch_manifest_hash).await?;
+    let patch_manifest = PatchManifest::read(&mut Cursor::new(patch_manifest))?;
+
+    let patch_entry = patch_manifest
+        .find(&args.content_key.clone().into())
+        .ok_or(anyhow::anyhow!("no patches for {:?}", args.content_key))?;
+
+    let cdn_config = download_config(&selected_cdn, &version_definition.cdn_config).await?;
+    let cdn_config = parse_cdn_config(&cdn_config)?;
+    let patch_archive_resolver = match load_archive_indices(
+        &selected_cdn,
+        &cdn_config.patch_archives,
+        cdn_config.patch_archive_group.as_ref(),
+        patch_url,
+    )
+    .await
+    {
+        Ok(resolver) => resolver,
+        Err(e) => {
+            tracing::warn!("failed to load patch archive indices, using loose patches: {e}");
+            ArchiveResolver::new()
+        }
+    };
+
+    let old_data = tokio::fs::read(&args.input).await?;
+    // the older version is only known by its contents, so every patch from a file
+    // of the same size is tried until one produces the expected content key
+    let candidates = patch_entry
+        .patches
+        .iter()
+        .filter(|patch| patch.source_size == old_data.len() as u64);
+    let mut new_data = None;
+    for file_patch in candidates {
+        let Some(patch_e_key) = file_patch.patch_e_key.to_md5() else {
+            tracing::warn!("skipping truncated patch key {:?}", file_patch.patch_e_key);
+            continue;
+        };
+        tracing::debug!(
+            "applying patch {patch_e_key:?} from {:?}",
+            file_patch.source_e_key
+        );
+
+        let patch_data =
+            match download_file_patch(&selected_cdn, &patch_e_key, &patch_archive_resolver).await {
+                Ok(patch_data) => patch_data,
+                Err(e) => {
+                    tracing::warn!("failed to download patch {patch_e_key:?}: {e}");
+                    continue;
+                }
+            };
+        let patched = match zbsdiff::apply(&old_data, &patch_data) {
+            Ok(patched) => patched,
+            Err(e) => {
+                tracing::debug!("patch {patch_e_key:?} does not apply: {e}");
+                continue;
+            }
+        };
+        let c_key = Md5Hash(Md5::digest(&patched).into());
+        if c_key == args.content_key {
+            new_data = Some(patched);
+            break;
+        }
+        tracing::debug!("patch {patch_e_key:?} produced content key {c_key:?}");
+    }
+    let new_data = new_data.ok_or(anyhow::anyhow!(
+        "no patch from {} produces {:?}",
+        args.input.display(),
+        args.content_key
+    ))?;
+
+    let output_dir = args
+        .output
+        .join(args.product.cdn_path())
+        .join(&version_definition.version_name);
+    tokio::fs::create_dir_all(&output_dir).await?;
+    tokio::fs::write(output_dir.join(args.content_key.as_str()), &new_data).await?;
+    tracing::debug!(
+        "successfully upgraded content key: {:?} with size: {}",
+        &args.content_key,
+        new_data.len()
+    );
+    Ok(())
+}
+
+/// Latest build of `product`, along with the cdn it is downloaded from and its version
+async fn latest_build(
+    product: Product,
+) -> anyhow::Result<(String, VersionDefinition, BuildConfig)> {
+    let url = format!("http://us.patch.battle.net:1119/{}", product.cdn_path());
     let cdn_bytes = reqwest::get(format!("{url}/cdns")).await?.text().await?;
     let cdn_table = parse_cdn_table(&cdn_bytes)?;
     tracing::debug!("{cdn_table:#?}");
@@ -245,8 +469,7 @@ async fn download_command(args: DownloadArgs) -> anyhow::Result<()> {
     let selected_server = cdn_definition
         .servers
         .into_iter()
-        .filter(|server| server.contains(".cdn"))
-        .next()
+        .find(|server| server.contains(".cdn"))
         .ok_or(anyhow::anyhow!("atleast one server entry"))?;
     let version_definition = version_table
         .into_iter()
@@ -254,38 +477,13 @@ async fn download_command(args: DownloadArgs) -> anyhow::Result<()> {
         .ok_or(anyhow::anyhow!("atleast one version entry"))?;
 
     tracing::debug!("latest version: {}", &version_definition.version_name);
-    let output_dir = args
-        .output
-        .join(args.product.cdn_path())
-        .join(&version_definition.version_name);
-
-    tracing::debug!("output dir: {output_dir:?}");
-    if !Path::new(&output_dir).exists() {
-        std::fs::create_dir_all(&output_dir)?;
-    }
     let selected_cdn = format!("{}/{}", selected_server, cdn_definition.path);
     tracing::debug!("selected cdn: {selected_cdn}");
 
-    let build_config_hash = version_definition.build_config;
-    let build_config = download_config(&selected_cdn, &build_config_hash).await?;
+    let build_config = download_config(&selected_cdn, &version_definition.build_config).await?;
     let build_config = parse_build_config(&build_config)?;
     tracing::debug!("{build_config:#?}");
-
-    let encoding_config_hash = build_config.encoding.1;
-    let table_data = download_by_ekey(&selected_cdn, &encoding_config_hash).await?;
-    let encoding_table: EncodingManifest = EncodingManifest::read(&mut Cursor::new(table_data))?;
-
-    tracing::debug!("beginning download of content key: {:?}", args.content_key);
-    let data = download_by_ckey(&selected_cdn, &args.content_key, &encoding_table).await?;
-    tracing::debug!(
-        "successfully downloaded content key: {:?} with size: {}",
-        &args.content_key,
-        data.len()
-    );
-
-    let path = output_dir.join(args.content_key.as_str());
-    let mut output_file = std::fs::File::create(path)?;
-    Ok(output_file.write_all(&data)?)
+    Ok((selected_cdn, version_definition, build_config))
 }
 
 async fn download_config(selected_cdn: &str, e_key: &Md5Hash) -> anyhow::Result<String> {
@@ -300,39 +498,290 @@ async fn download_config(selected_cdn: &str, e_key: &Md5Hash) -> anyhow::Result<
     Ok(bytes)
 }
 
-async fn download_by_ekey(selected_cdn: &str, e_key: &Md5Hash) -> anyhow::Result<Vec<u8>> {
-    let e_key = e_key.as_str();
-    let file_url = format!(
-        "https://{selected_cdn}/data/{}/{}/{e_key}",
-        &e_key[0..2],
-        &e_key[2..4]
-    );
+/// Downloads a patch manifest or loose patch from the cdn's patch directory
+async fn download_patch(selected_cdn: &str, key: &Md5Hash) -> anyhow::Result<bytes::Bytes> {
+    let file_url = patch_url(selected_cdn, key);
     tracing::debug!("requesting {file_url}");
-    let blte_bytes = reqwest::get(file_url).await?.bytes().await?;
-    let block_table: BlockTable = BlockTable::read(&mut Cursor::new(blte_bytes))?;
-    let table_data = block_table.decompress()?;
+    Ok(reqwest::get(file_url)
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?)
+}
 
+/// Downloads a patch from the patch archive `patch_archive_resolver` locates it in,
+/// otherwise the loose patch
+async fn download_file_patch(
+    selected_cdn: &str,
+    patch_e_key: &Md5Hash,
+    patch_archive_resolver: &ArchiveResolver,
+) -> anyhow::Result<bytes::Bytes> {
+    match patch_archive_resolver.resolve(&patch_e_key.into()) {
+        Some(location) => {
+            tracing::debug!("{patch_e_key:?} found in patch archive {location:?}");
+            let url = patch_url(selected_cdn, &location.archive);
+            request_range(&reqwest::Client::new(), &url, location.range()).await
+        }
+        None => {
+            tracing::debug!(
+                "{patch_e_key:?} not found in any patch archive, using the loose patch"
+            );
+            download_patch(selected_cdn, patch_e_key).await
+        }
+    }
+}
+
+/// Url of a loose patch or patch archive in the cdn's patch directory
+fn patch_url(selected_cdn: &str, key: &Md5Hash) -> String {
+    let key = key.as_str();
+    format!(
+        "https://{selected_cdn}/patch/{}/{}/{key}",
+        &key[0..2],
+        &key[2..4]
+    )
+}
+
+/// Url of a loose file or archive in the cdn's data directory
+fn data_url(selected_cdn: &str, key: &Md5Hash) -> String {
+    let key = key.as_str();
+    format!(
+        "https://{selected_cdn}/data/{}/{}/{key}",
+        &key[0..2],
+        &key[2..4]
+    )
+}
+
+/// Where the blte encoded bytes of a file are fetched from
+struct BlteSource {
+    url: String,
+    /// Byte range of the file inside an archive, None for a loose file
+    range: Option<Range<u64>>,
+}
+
+impl BlteSource {
+    /// Loose file stored under its own encoding key
+    fn loose(selected_cdn: &str, e_key: &Md5Hash) -> Self {
+        Self {
+            url: data_url(selected_cdn, e_key),
+            range: None,
+        }
+    }
+
+    /// Inside the archive `archive_resolver` locates `e_key` in, otherwise a loose file
+    fn resolve(selected_cdn: &str, e_key: &Md5Hash, archive_resolver: &ArchiveResolver) -> Self {
+        match archive_resolver.resolve(&e_key.into()) {
+            Some(location) => {
+                tracing::debug!("{e_key:?} found in archive {location:?}");
+                Self {
+                    url: data_url(selected_cdn, &location.archive),
+                    range: Some(location.range()),
+                }
+            }
+            None => {
+                tracing::debug!("{e_key:?} not found in any archive, using the loose file");
+                Self::loose(selected_cdn, e_key)
+            }
+        }
+    }
+
+    /// Offset of the file's first byte in `url`
+    fn base_offset(&self) -> u64 {
+        self.range.as_ref().map_or(0, |range| range.start)
+    }
+}
+
+/// Downloads the index of `archive_group` when there is one,
+/// otherwise the index of every archive, skipping indices which fail to parse
+/// `archive_url` locates an archive, its index is stored next to it
+async fn load_archive_indices(
+    selected_cdn: &str,
+    archives: &[Md5Hash],
+    archive_group: Option<&Md5Hash>,
+    archive_url: fn(&str, &Md5Hash) -> String,
+) -> anyhow::Result<ArchiveResolver> {
+    if let Some(archive_group) = archive_group {
+        let url = format!("{}.index", archive_url(selected_cdn, archive_group));
+        tracing::debug!("requesting {url}");
+        let data = reqwest::get(url).await?.error_for_status()?.bytes().await?;
+        let index = IndexFile::parse(&data)?;
+        anyhow::ensure!(
+            index.is_archive_group(),
+            "archive-group {archive_group:?} is not an archive-group index"
+        );
+
+        let mut archive_resolver = ArchiveResolver::new();
+        archive_resolver.add_group_index(archives, &index);
+        tracing::debug!(
+            "indexed {} encoding keys from archive-group {archive_group:?}",
+            archive_resolver.len()
+        );
+        return Ok(archive_resolver);
+    }
+
+    let mut indices = futures_util::stream::iter(archives)
+        .map(|archive| async move {
+            let url = format!("{}.index", archive_url(selected_cdn, archive));
+            tracing::trace!("requesting {url}");
+            let data = reqwest::get(url).await?.error_for_status()?.bytes().await?;
+            anyhow::Ok((archive, data))
+        })
+        .buffer_unordered(16);
+
+    let mut archive_resolver = ArchiveResolver::new();
+    while let Some(result) = indices.next().await {
+        let (archive, data) = result?;
+        match IndexFile::parse(&data) {
+            Ok(index) => archive_resolver.add_index(archive, &index),
+            Err(e) => tracing::warn!("skipping index of archive {archive:?}: {e}"),
+        }
+    }
     tracing::debug!(
-        "successfully read client executable and decompressed to {} bytes",
-        table_data.len()
+        "indexed {} encoding keys across {} archives",
+        archive_resolver.len(),
+        archives.len()
     );
+    Ok(archive_resolver)
+}
+
+/// Downloads and decodes a blte encoded file into memory
+/// `decoded_size` is the expected size of the decoded file, used to pre-allocate
+async fn download_blte(
+    source: &BlteSource,
+    c_key: &Md5Hash,
+    decoded_size: u64,
+    decode_options: &DecodeOptions<'_>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut table_data = Vec::with_capacity(decoded_size as usize);
+    download_blte_into(source, c_key, decode_options, &mut table_data).await?;
     Ok(table_data)
 }
 
-async fn download_by_ckey(
-    selected_cdn: &str,
+/// Streams a blte encoded file, writing each chunk to `output` as soon as it is decoded
+/// The decoded file is checked against its content key `c_key` once fully written
+async fn download_blte_into<W: AsyncWrite + Unpin>(
+    source: &BlteSource,
+    c_key: &Md5Hash,
+    decode_options: &DecodeOptions<'_>,
+    output: &mut W,
+) -> anyhow::Result<u64> {
+    let mut request = reqwest::Client::new().get(&source.url);
+    if let Some(range) = &source.range {
+        request = request.header(
+            reqwest::header::RANGE,
+            format!("bytes={}-{}", range.start, range.end - 1),
+        );
+    }
+    tracing::debug!("requesting {} bytes {:?}", source.url, source.range);
+    let response = request.send().await?.error_for_status()?;
+    if source.range.is_some() {
+        ensure_partial_content(&response)?;
+    }
+    let blte_stream = response.bytes_stream().map_err(std::io::Error::other);
+    let mut blte_reader =
+        AsyncBlteReader::with_options(StreamReader::new(blte_stream), *decode_options).await?;
+
+    let mut decoded_size = 0;
+    let mut hasher = Md5::new();
+    while let Some(chunk) = blte_reader.next_chunk().await? {
+        hasher.update(&chunk);
+        output.write_all(&chunk).await?;
+        decoded_size += chunk.len() as u64;
+    }
+    let decoded_key = Md5Hash(hasher.finalize().into());
+    anyhow::ensure!(
+        &decoded_key == c_key,
+        "{} decoded to content key {decoded_key:?}, expected {c_key:?}",
+        source.url
+    );
+
+    tracing::debug!("successfully read and decompressed {decoded_size} bytes");
+    Ok(decoded_size)
+}
+
+/// Downloads and decodes only the chunks covering `range` through http range requests
+/// Single chunk files are downloaded whole and checked against their content key `c_key`
+async fn download_blte_range(
+    source: &BlteSource,
     c_key: &Md5Hash,
-    encoding_table: &EncodingManifest,
+    range: Range<u64>,
+    decode_options: &DecodeOptions<'_>,
 ) -> anyhow::Result<Vec<u8>> {
+    let client = reqwest::Client::new();
+    let base = source.base_offset();
+    let encoded_range = |range: Range<u64>| range.start + base..range.end + base;
+
+    let prefix = request_range(&client, &source.url, encoded_range(0..8)).await?;
+    let header_size = u32::from_be_bytes(prefix[4..8].try_into()?);
+    if header_size == 0 {
+        tracing::debug!("single chunk file, falling back to a full download");
+        let mut data = download_blte(source, c_key, 0, decode_options).await?;
+        let end = (range.end as usize).min(data.len());
+        data.truncate(end);
+        data.drain(..(range.start as usize).min(end));
+        return Ok(data);
+    }
+
+    let header_range = encoded_range(0..u64::from(header_size));
+    let header_data = request_range(&client, &source.url, header_range).await?;
+    let header = BlteHeader::read(&mut Cursor::new(header_data))?;
+    let Some(span) = header.chunk_span(range.clone()) else {
+        return Ok(Vec::new());
+    };
+    tracing::debug!("decoding chunks {:?} for range {range:?}", span.chunks);
+
+    let encoded_data =
+        request_range(&client, &source.url, encoded_range(span.encoded.clone())).await?;
+    Ok(header.decode_span(&span, &encoded_data, range, decode_options)?)
+}
+
+async fn request_range(
+    client: &reqwest::Client,
+    file_url: &str,
+    range: Range<u64>,
+) -> anyhow::Result<bytes::Bytes> {
+    tracing::debug!("requesting {file_url} bytes {range:?}");
+    let response = client
+        .get(file_url)
+        .header(
+            reqwest::header::RANGE,
+            format!("bytes={}-{}", range.start, range.end - 1),
+        )
+        .send()
+        .await?
+        .error_for_status()?;
+    ensure_partial_content(&response)?;
+    let data = response.bytes().await?;
+    anyhow::ensure!(
+        data.len() as u64 == range.end - range.start,
+        "{file_url} answered {} bytes for range {range:?}",
+        data.len()
+    );
+    Ok(data)
+}
+
+/// Servers ignoring the Range header answer with the whole file, which must not be decoded as the range
+fn ensure_partial_content(response: &reqwest::Response) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        response.status() == reqwest::StatusCode::PARTIAL_CONTENT,
+        "{} answered a range request with {}",
+        response.url(),
+        response.status()
+    );
+    Ok(())
+}
+
+/// Looks up the first encoding key of `c_key` along with its decoded size
+fn find_ekey(encoding_table: &EncodingManifest, c_key: &Md5Hash) -> anyhow::Result<(Md5Hash, u64)> {
     let encoding_entry = encoding_table
-        .ce_key_table_entries
-        .iter()
-        .find(|ce_entry| ce_entry.c_key == *c_key)
+        .find_ckey(&c_key.into())?
         .ok_or(anyhow::anyhow!("has ce table entry"))?;
 
     let e_key = encoding_entry
         .e_keys
-        .first()
-        .ok_or(anyhow::anyhow!("has ekey"))?;
-    download_by_ekey(selected_cdn, e_key).await
+        .into_iter()
+        .next()
+        .ok_or(anyhow::anyhow!("has ekey"))?
+        .to_md5()
+        .ok_or(anyhow::anyhow!("ekey is truncated"))?;
+    Ok((e_key, encoding_entry.file_size))
 }
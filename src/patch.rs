@@ -0,0 +1,269 @@
+use binrw::io::{Read, SeekFrom};
+use binrw::{BinRead, BinResult};
+
+use crate::{u40_parser, EncodingError, EncodingManifest, Key, Md5Hash};
+
+/// Patch manifest, the patches available to produce each file of a build from older builds
+#[derive(Debug, BinRead)]
+#[br(big, magic = b"PA")]
+pub struct PatchManifest {
+    pub version: u8,
+    pub file_key_size: u8,
+    pub old_key_size: u8,
+    pub patch_key_size: u8,
+    #[br(assert(block_size_bits < 32, "invalid block size {}", block_size_bits))]
+    pub block_size_bits: u8,
+    pub block_count: u16,
+    pub flags: u8,
+    #[br(args(file_key_size))]
+    pub encoding_c_key: Key,
+    #[br(args(file_key_size))]
+    pub encoding_e_key: Key,
+    pub decoded_size: u32,
+    pub encoded_size: u32,
+    pub espec_size: u8,
+    #[br(count = usize::from(espec_size), try_map = String::from_utf8)]
+    pub espec: String,
+    #[br(count = usize::from(block_count), args { inner: (file_key_size,) })]
+    pub block_headers: Vec<PatchBlockHeader>,
+    /// Entries of every block, sorted by target content key
+    #[br(
+        parse_with = patch_entries_parser,
+        args(block_headers.clone(), block_size_bits, (file_key_size, old_key_size, patch_key_size))
+    )]
+    pub entries: Vec<PatchFileEntry>,
+}
+
+#[derive(Debug, Clone, BinRead)]
+#[br(import(key_size: u8))]
+pub struct PatchBlockHeader {
+    /// Content key of the last file in the block
+    #[br(args(key_size))]
+    pub last_file_c_key: Key,
+    pub block_md5: Md5Hash,
+    /// Offset of the block from the start of the manifest
+    pub block_offset: u32,
+}
+
+/// Patches available to produce a single file
+#[derive(Debug, Clone, BinRead)]
+#[br(import(file_key_size: u8, old_key_size: u8, patch_key_size: u8))]
+pub struct PatchFileEntry {
+    pub num_patches: u8,
+    #[br(args(file_key_size))]
+    pub target_c_key: Key,
+    #[br(parse_with = u40_parser)]
+    pub decoded_size: u64,
+    #[br(count = usize::from(num_patches), args { inner: (old_key_size, patch_key_size) })]
+    pub patches: Vec<FilePatch>,
+}
+
+/// Patch producing a file from an older version of it
+#[derive(Debug, Clone, BinRead)]
+#[br(import(old_key_size: u8, patch_key_size: u8))]
+pub struct FilePatch {
+    /// Encoding key of the older version
+    #[br(args(old_key_size))]
+    pub source_e_key: Key,
+    /// Decoded size of the older version
+    #[br(parse_with = u40_parser)]
+    pub source_size: u64,
+    /// Encoding key of the patch, stored in a patch archive or loose at `patch/xx/yy/<patch_e_key>`
+    #[br(args(patch_key_size))]
+    pub patch_e_key: Key,
+    pub patch_size: u32,
+    pub patch_index: u8,
+}
+
+/// Reads the entries of every block, each block ends at its size or an entry without patches,
+/// anything else which fails to parse is an error
+#[binrw::parser(reader, endian)]
+fn patch_entries_parser(
+    block_headers: Vec<PatchBlockHeader>,
+    block_size_bits: u8,
+    key_sizes: (u8, u8, u8),
+) -> BinResult<Vec<PatchFileEntry>> {
+    let block_size = 1u64 << block_size_bits;
+    let mut entries = Vec::new();
+    for header in &block_headers {
+        reader.seek(SeekFrom::Start(u64::from(header.block_offset)))?;
+        let mut block = Vec::new();
+        (&mut *reader).take(block_size).read_to_end(&mut block)?;
+
+        let block_len = block.len() as u64;
+        let mut cursor = binrw::io::Cursor::new(block);
+        while cursor.position() < block_len {
+            let entry = PatchFileEntry::read_options(&mut cursor, endian, key_sizes)?;
+            if entry.num_patches == 0 {
+                break;
+            }
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+impl PatchManifest {
+    /// Looks up the patches producing the file with content key `c_key`
+    pub fn find(&self, c_key: &Key) -> Option<&PatchFileEntry> {
+        let c_key = c_key.truncated(self.file_key_size);
+        self.entries
+            .binary_search_by(|entry| entry.target_c_key.as_bytes().cmp(c_key.as_bytes()))
+            .ok()
+            .map(|index| &self.entries[index])
+    }
+
+    /// Every file of this build which can be patched from a file of the build `old_encoding` describes,
+    /// along with the patch to apply
+    pub fn patches_from<'a>(
+        &'a self,
+        old_encoding: &EncodingManifest,
+    ) -> Result<Vec<(&'a PatchFileEntry, &'a FilePatch)>, EncodingError> {
+        let mut patches = Vec::new();
+        for entry in &self.entries {
+            for patch in &entry.patches {
+                if old_encoding.find_ekey(&patch.source_e_key)?.is_some() {
+                    patches.push((entry, patch));
+                    break;
+                }
+            }
+        }
+        Ok(patches)
+    }
+}
+
+impl PatchFileEntry {
+    /// Patch producing this file from the older version with encoding key `source_e_key`
+    pub fn patch_from(&self, source_e_key: &Key) -> Option<&FilePatch> {
+        self.patches
+            .iter()
+            .find(|patch| patch.source_e_key.matches(source_e_key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use md5::{Digest, Md5};
+
+    use super::*;
+    use crate::index::tests::test_key;
+
+    const BLOCK_SIZE_BITS: u8 = 9;
+    const ENTRIES_PER_BLOCK: u32 = 3;
+
+    /// Entry of file `index`, patched from `index % 3 + 1` older versions
+    fn build_entry(index: u32) -> Vec<u8> {
+        let num_patches = index % 3 + 1;
+        let mut entry = vec![num_patches as u8];
+        entry.extend_from_slice(&test_key(index));
+        entry.extend_from_slice(&(u64::from(index) * 10).to_be_bytes()[3..]);
+        for patch in 0..num_patches {
+            let mut source_e_key = test_key(index);
+            source_e_key[15] = patch as u8;
+            entry.extend_from_slice(&source_e_key);
+            entry.extend_from_slice(&(u64::from(index) * 10 + u64::from(patch)).to_be_bytes()[3..]);
+            entry.extend_from_slice(&[0xAA; 16]);
+            entry.extend_from_slice(&(index + patch).to_be_bytes());
+            entry.push(patch as u8);
+        }
+        entry
+    }
+
+    /// Patch manifest of `count` files, the last block is left unpadded
+    fn build_manifest(count: u32) -> Vec<u8> {
+        let indices: Vec<u32> = (0..count).collect();
+        let mut blocks: Vec<Vec<u8>> = indices
+            .chunks(ENTRIES_PER_BLOCK as usize)
+            .map(|chunk| chunk.iter().flat_map(|&index| build_entry(index)).collect())
+            .collect();
+        let last = blocks.len() - 1;
+        for block in &mut blocks[..last] {
+            assert!(block.len() < 1 << BLOCK_SIZE_BITS);
+            block.resize(1 << BLOCK_SIZE_BITS, 0);
+        }
+
+        let espec = b"b:{*=z}";
+        let mut data = b"PA".to_vec();
+        data.extend_from_slice(&[2, 16, 16, 16, BLOCK_SIZE_BITS]);
+        data.extend_from_slice(&(blocks.len() as u16).to_be_bytes());
+        data.push(0);
+        data.extend_from_slice(&[0xCC; 16]);
+        data.extend_from_slice(&[0xEE; 16]);
+        data.extend_from_slice(&1000u32.to_be_bytes());
+        data.extend_from_slice(&900u32.to_be_bytes());
+        data.push(espec.len() as u8);
+        data.extend_from_slice(espec);
+
+        let mut block_offset = data.len() + blocks.len() * (16 + 16 + 4);
+        for (chunk, block) in indices.chunks(ENTRIES_PER_BLOCK as usize).zip(&blocks) {
+            data.extend_from_slice(&test_key(*chunk.last().unwrap()));
+            data.extend_from_slice(&Md5::digest(block));
+            data.extend_from_slice(&(block_offset as u32).to_be_bytes());
+            block_offset += block.len();
+        }
+        data.extend(blocks.concat());
+        data
+    }
+
+    fn parse(data: Vec<u8>) -> BinResult<PatchManifest> {
+        PatchManifest::read(&mut binrw::io::Cursor::new(data))
+    }
+
+    #[test]
+    fn parses_patch_manifests() {
+        let manifest = parse(build_manifest(8)).unwrap();
+        assert_eq!(manifest.block_headers.len(), 3);
+        assert_eq!(manifest.espec, "b:{*=z}");
+        assert_eq!((manifest.decoded_size, manifest.encoded_size), (1000, 900));
+        assert_eq!(manifest.entries.len(), 8);
+
+        for (index, entry) in manifest.entries.iter().enumerate() {
+            let index = index as u32;
+            assert_eq!(entry.target_c_key.as_bytes(), test_key(index));
+            assert_eq!(entry.decoded_size, u64::from(index) * 10);
+            assert_eq!(entry.patches.len(), (index % 3 + 1) as usize);
+            for (patch_index, patch) in entry.patches.iter().enumerate() {
+                assert_eq!(
+                    patch.source_size,
+                    u64::from(index) * 10 + patch_index as u64
+                );
+                assert_eq!(patch.patch_e_key.as_bytes(), [0xAA; 16]);
+                assert_eq!(patch.patch_size, index + patch_index as u32);
+                assert_eq!(patch.patch_index, patch_index as u8);
+            }
+        }
+    }
+
+    #[test]
+    fn finds_patches() {
+        let manifest = parse(build_manifest(8)).unwrap();
+        let entry = manifest.find(&Md5Hash(test_key(5)).into()).unwrap();
+        assert_eq!(entry.decoded_size, 50);
+
+        let mut source_e_key = test_key(5);
+        source_e_key[15] = 2;
+        let patch = entry.patch_from(&Md5Hash(source_e_key).into()).unwrap();
+        assert_eq!(patch.patch_size, 7);
+
+        source_e_key[15] = 3;
+        assert!(entry.patch_from(&Md5Hash(source_e_key).into()).is_none());
+        assert!(manifest.find(&Md5Hash(test_key(8)).into()).is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_entries() {
+        let mut data = build_manifest(8);
+        data.truncate(data.len() - 10);
+        assert!(parse(data).is_err());
+    }
+
+    #[test]
+    fn rejects_corrupt_entries() {
+        let mut data = build_manifest(8);
+        let second_block =
+            data.len() - build_entry(6).len() - build_entry(7).len() - (1 << BLOCK_SIZE_BITS);
+        // Claims more patches than the rest of the second block holds
+        data[second_block] = 0xFF;
+        assert!(parse(data).is_err());
+    }
+}
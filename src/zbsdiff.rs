@@ -0,0 +1,195 @@
+use std::io::{Cursor, Read};
+
+use binrw::{BinRead, BinResult};
+use flate2::read::ZlibDecoder;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ZbsdiffError {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+
+    #[error("binread error")]
+    BinRead(#[from] binrw::Error),
+
+    #[error("corrupt patch, {0}")]
+    Corrupt(&'static str),
+}
+
+/// Parses a bsdiff sign-magnitude integer, little endian with the sign in the top bit
+#[binrw::parser(reader)]
+fn offtin_parser() -> BinResult<i64> {
+    let value = u64::read_options(reader, binrw::Endian::Little, ())?;
+    let magnitude = (value & !(1 << 63)) as i64;
+    Ok(if value >> 63 == 1 {
+        -magnitude
+    } else {
+        magnitude
+    })
+}
+
+#[derive(Debug, BinRead)]
+#[br(little, magic = b"ZBSDIFF1")]
+pub struct ZbsdiffHeader {
+    #[br(parse_with = offtin_parser, assert(ctrl_block_size >= 0, "negative block size"))]
+    pub ctrl_block_size: i64,
+    #[br(parse_with = offtin_parser, assert(diff_block_size >= 0, "negative block size"))]
+    pub diff_block_size: i64,
+    #[br(parse_with = offtin_parser, assert(new_size >= 0, "negative size"))]
+    pub new_size: i64,
+}
+
+impl ZbsdiffHeader {
+    pub const SIZE: usize = 32;
+}
+
+/// Control tuple, add `diff` bytes, copy `extra` bytes, then seek the old file by `seek`
+#[derive(Debug, BinRead)]
+#[br(little)]
+struct Control {
+    #[br(parse_with = offtin_parser)]
+    diff: i64,
+    #[br(parse_with = offtin_parser)]
+    extra: i64,
+    #[br(parse_with = offtin_parser)]
+    seek: i64,
+}
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>, ZbsdiffError> {
+    let mut output = Vec::new();
+    ZlibDecoder::new(data).read_to_end(&mut output)?;
+    Ok(output)
+}
+
+/// Applies a ZBSDIFF1 patch to `old`, producing the new file
+pub fn apply(old: &[u8], patch: &[u8]) -> Result<Vec<u8>, ZbsdiffError> {
+    let header = ZbsdiffHeader::read(&mut Cursor::new(patch))?;
+    let ctrl_end = ZbsdiffHeader::SIZE
+        .checked_add(header.ctrl_block_size as usize)
+        .ok_or(ZbsdiffError::Corrupt("ctrl block out of range"))?;
+    let diff_end = ctrl_end
+        .checked_add(header.diff_block_size as usize)
+        .filter(|&end| end <= patch.len())
+        .ok_or(ZbsdiffError::Corrupt("diff block out of range"))?;
+
+    let ctrl = inflate(&patch[ZbsdiffHeader::SIZE..ctrl_end])?;
+    let diff = inflate(&patch[ctrl_end..diff_end])?;
+    let extra = inflate(&patch[diff_end..])?;
+
+    let new_size = header.new_size as usize;
+    // every new byte comes from either the diff or extra block
+    let mut new = Vec::with_capacity(new_size.min(diff.len().saturating_add(extra.len())));
+    let (mut diff_pos, mut extra_pos, mut old_pos) = (0usize, 0usize, 0i64);
+    let mut ctrl = Cursor::new(ctrl);
+    while new.len() < new_size {
+        let control = Control::read(&mut ctrl)?;
+        let (Ok(diff_len), Ok(extra_len)) = (
+            usize::try_from(control.diff),
+            usize::try_from(control.extra),
+        ) else {
+            return Err(ZbsdiffError::Corrupt("negative control length"));
+        };
+        new.len()
+            .checked_add(diff_len)
+            .and_then(|len| len.checked_add(extra_len))
+            .filter(|&len| len <= new_size)
+            .ok_or(ZbsdiffError::Corrupt("control exceeds new size"))?;
+
+        let diff_block = diff_pos
+            .checked_add(diff_len)
+            .and_then(|diff_end| diff.get(diff_pos..diff_end))
+            .ok_or(ZbsdiffError::Corrupt("diff block exhausted"))?;
+        for (i, &byte) in diff_block.iter().enumerate() {
+            // bytes outside of the old file are added to zero
+            let old_byte = old_pos
+                .checked_add(i as i64)
+                .and_then(|pos| usize::try_from(pos).ok())
+                .and_then(|pos| old.get(pos))
+                .copied()
+                .unwrap_or(0);
+            new.push(byte.wrapping_add(old_byte));
+        }
+        diff_pos += diff_len;
+
+        let extra_block = extra_pos
+            .checked_add(extra_len)
+            .and_then(|extra_end| extra.get(extra_pos..extra_end))
+            .ok_or(ZbsdiffError::Corrupt("extra block exhausted"))?;
+        new.extend_from_slice(extra_block);
+        extra_pos += extra_len;
+
+        old_pos = old_pos
+            .checked_add(control.diff)
+            .and_then(|pos| pos.checked_add(control.seek))
+            .ok_or(ZbsdiffError::Corrupt("old file position out of range"))?;
+    }
+    Ok(new)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Encodes a bsdiff sign-magnitude integer
+    fn offtout(value: i64) -> [u8; 8] {
+        let magnitude = value.unsigned_abs() & !(1 << 63);
+        let sign = if value < 0 { 1 << 63 } else { 0 };
+        (magnitude | sign).to_le_bytes()
+    }
+
+    fn build_patch(
+        controls: &[(i64, i64, i64)],
+        diff: &[u8],
+        extra: &[u8],
+        new_size: i64,
+    ) -> Vec<u8> {
+        let ctrl: Vec<u8> = controls
+            .iter()
+            .flat_map(|&(diff, extra, seek)| [offtout(diff), offtout(extra), offtout(seek)])
+            .flatten()
+            .collect();
+        let (ctrl, diff, extra) = (deflate(&ctrl), deflate(diff), deflate(extra));
+
+        let mut patch = b"ZBSDIFF1".to_vec();
+        patch.extend_from_slice(&offtout(ctrl.len() as i64));
+        patch.extend_from_slice(&offtout(diff.len() as i64));
+        patch.extend_from_slice(&offtout(new_size));
+        patch.extend_from_slice(&ctrl);
+        patch.extend_from_slice(&diff);
+        patch.extend_from_slice(&extra);
+        patch
+    }
+
+    #[test]
+    fn applies_patches() {
+        let old = b"hello old world";
+        // "hello" unchanged, " new" from the extra block, then skip back over "old " to " world"
+        let patch = build_patch(&[(5, 4, 4), (6, 0, 0)], &[0; 11], b" new", 15);
+        assert_eq!(apply(old, &patch).unwrap(), b"hello new world");
+    }
+
+    #[test]
+    fn rejects_overflowing_controls() {
+        let patches = [
+            build_patch(&[(0, 2, 0), (i64::MAX, i64::MAX, 0)], &[], b"ab", 4),
+            build_patch(&[(1, 0, i64::MAX), (1, 0, 0)], &[0; 2], &[], 2),
+            build_patch(&[(0, 1, i64::MIN + 1), (0, 1, -2)], &[], b"ab", 2),
+        ];
+        for patch in patches {
+            assert!(matches!(
+                apply(b"old", &patch),
+                Err(ZbsdiffError::Corrupt(_))
+            ));
+        }
+    }
+}